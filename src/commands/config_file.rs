@@ -0,0 +1,435 @@
+use crate::config::{self, ConfigManager};
+use crate::models::{Config, ConfigFormat, ConflictPolicy, Device, Secret, SettingSource, Settings};
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use super::{confirm, App};
+
+#[derive(Serialize)]
+struct ValidationReport {
+    config_file: PathBuf,
+    devices: usize,
+    issues: Vec<config::ConfigIssue>,
+    errors: usize,
+    warnings: usize,
+}
+
+impl App {
+    pub async fn validate_config(&self, strict: bool, json_output: bool) -> Result<()> {
+        let config = self.config()?;
+        let loaded = config.load_config()?;
+        let issues = config::validate_config(&loaded);
+        let errors = issues.iter().filter(|i| i.severity == config::Severity::Error).count();
+        let report = ValidationReport {
+            config_file: config.config_path().to_path_buf(),
+            devices: loaded.devices.len(),
+            warnings: issues.len() - errors,
+            errors,
+            issues,
+        };
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else if report.issues.is_empty() {
+            println!("✅ {} is valid ({} devices)", report.config_file.display(), report.devices);
+        } else {
+            println!("Problems found in {}:", report.config_file.display());
+            for issue in &report.issues {
+                let marker = match issue.severity {
+                    config::Severity::Error => "❌",
+                    config::Severity::Warning => "⚠️ ",
+                };
+                match &issue.device {
+                    Some(device) => println!("{} [devices.{}] {}", marker, device, issue.problem),
+                    None => println!("{} {}", marker, issue.problem),
+                }
+                println!("   Fix: {}", issue.fix);
+            }
+            println!();
+            println!("{} error(s), {} warning(s)", report.errors, report.warnings);
+        }
+
+        // Warnings alone only fail the check with --strict
+        if report.errors > 0 || (strict && report.warnings > 0) {
+            bail!("{} error(s) and {} warning(s) found in config", report.errors, report.warnings);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct ConfigPaths {
+    config_file: PathBuf,
+    config_dir: PathBuf,
+    data_dir: PathBuf,
+    history_dir: PathBuf,
+    shell_history: PathBuf,
+    known_hosts: PathBuf,
+    backups_dir: PathBuf,
+    cache_dir: PathBuf,
+}
+
+impl App {
+    pub async fn show_config_paths(&self, json_output: bool) -> Result<()> {
+        let config = self.config()?;
+        let paths = ConfigPaths {
+            config_file: config.config_path().to_path_buf(),
+            config_dir: config.config_dir().to_path_buf(),
+            data_dir: config.data_dir().to_path_buf(),
+            history_dir: config.history_dir(),
+            shell_history: config.shell_history_path(),
+            known_hosts: config.known_hosts_path(),
+            backups_dir: config.device_backups_dir(self.options.backup_dir.as_deref()),
+            cache_dir: config.cache_dir(),
+        };
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&paths)?);
+            return Ok(());
+        }
+
+        println!("📄 Config file: {}", paths.config_file.display());
+        println!("📁 Config directory: {}", paths.config_dir.display());
+        println!("📁 Data directory: {}", paths.data_dir.display());
+        println!("📈 Status history: {}", paths.history_dir.display());
+        println!("⌨️  Shell history: {}", paths.shell_history.display());
+        println!("🔑 SSH host keys: {}", paths.known_hosts.display());
+        println!("💾 Device backups: {}", paths.backups_dir.display());
+        println!("🗄️  Cache: {}", paths.cache_dir.display());
+
+        Ok(())
+    }
+}
+
+const REDACTED: &str = "***";
+
+#[derive(Serialize)]
+struct EffectiveSetting {
+    // Unset when there is no value at all, e.g. no log file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<serde_json::Value>,
+    source: SettingSource,
+}
+
+#[derive(Serialize)]
+struct EffectiveConfig {
+    config_file: PathBuf,
+    backup_file: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default_device: Option<String>,
+    settings: std::collections::BTreeMap<&'static str, EffectiveSetting>,
+    devices: std::collections::BTreeMap<String, Device>,
+}
+
+// Mask everything that could be a credential: passwords, password commands
+// and header values (which often carry tokens)
+fn redact_device(mut device: Device) -> Device {
+    if !device.password.is_empty() {
+        device.password = Secret::new(REDACTED);
+    }
+    if device.password_command.is_some() {
+        device.password_command = Some(REDACTED.to_string());
+    }
+    for value in device.headers.values_mut() {
+        *value = REDACTED.to_string();
+    }
+    device
+}
+
+impl App {
+    pub async fn show_config(&self, json_output: bool, unredacted: bool) -> Result<()> {
+        let manager = self.config()?;
+        let config = manager.load_config()?;
+
+        let setting = |key: &'static str, value: serde_json::Value| {
+            let value = Some(value).filter(|value| !value.is_null());
+            (key, EffectiveSetting { value, source: self.options.source(key) })
+        };
+        let effective = EffectiveConfig {
+            config_file: manager.config_path().to_path_buf(),
+            backup_file: manager.backup_path(),
+            default_device: config.default_device,
+            settings: [
+                setting("connect_timeout", json!(self.options.connect_timeout)),
+                setting("output", json!(self.options.output)),
+                setting("concurrency", json!(self.options.concurrency)),
+                setting("backup_dir", json!(self.options.backup_dir)),
+                setting("logging.level", json!(self.options.log_level)),
+                setting("logging.file", json!(self.options.log_file)),
+            ]
+            .into_iter()
+            .collect(),
+            devices: config
+                .devices
+                .into_iter()
+                .map(|(key, device)| (key, if unredacted { device } else { redact_device(device) }))
+                .collect(),
+        };
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&effective)?);
+        } else {
+            print!("{}", toml::to_string_pretty(&effective)?);
+        }
+
+        Ok(())
+    }
+
+    pub async fn encrypt_config(&self) -> Result<()> {
+        let config = self.config()?;
+        config.encrypt_config()?;
+        println!("🔒 Encrypted {}", config.config_path().display());
+        println!("   Commands will now ask for the passphrase (or read WRTCLI_PASSPHRASE)");
+        Ok(())
+    }
+
+    pub async fn decrypt_config(&self) -> Result<()> {
+        let config = self.config()?;
+        config.decrypt_config()?;
+        println!("🔓 Decrypted {}", config.config_path().display());
+        Ok(())
+    }
+
+    pub async fn fix_permissions(&self) -> Result<()> {
+        let config = self.config()?;
+        let fixed = config.fix_permissions()?;
+        if fixed.is_empty() {
+            println!("✅ Permissions are already restricted to the current user");
+            return Ok(());
+        }
+
+        for path in &fixed {
+            println!("🔒 {}", path.display());
+        }
+        println!("✅ Restricted {} path(s) to the current user", fixed.len());
+        Ok(())
+    }
+
+    pub async fn set_setting(&self, key: &str, value: &str) -> Result<()> {
+        let config = self.config()?;
+        config.update_config(|config| config.settings.set(key, value))?;
+        println!("✅ Set {} = {}", key, value);
+        Ok(())
+    }
+
+    pub async fn unset_setting(&self, key: &str) -> Result<()> {
+        let config = self.config()?;
+        config.update_config(|config| config.settings.unset(key))?;
+        println!("✅ Unset {}; the default applies again", key);
+        Ok(())
+    }
+}
+
+// Print one setting, or every setting when no key is given
+impl App {
+    pub async fn get_setting(&self, key: Option<&str>) -> Result<()> {
+        let settings = self.config()?.load_config()?.settings;
+
+        if let Some(key) = key {
+            match settings.get(key)? {
+                Some(value) => println!("{}", value),
+                None => println!("(not set)"),
+            }
+            return Ok(());
+        }
+
+        for (key, description) in Settings::KEYS {
+            let value = settings.get(key)?.unwrap_or_else(|| "(not set)".to_string());
+            println!("{} = {}    # {}", key, value, description);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct ImportReport {
+    dry_run: bool,
+    decisions: Vec<config::ImportDecision>,
+}
+
+impl App {
+    pub async fn import_config(&self, file: &Path, on_conflict: ConflictPolicy, dry_run: bool, json_output: bool) -> Result<()> {
+        if !file.exists() {
+            bail!("{} does not exist", file.display());
+        }
+        let imported = ConfigManager::from_path(file.to_path_buf())?
+            .load_config()
+            .context(format!("Failed to read {}", file.display()))?;
+
+        let manager = self.config()?;
+        let decisions = manager.import_devices(imported.devices, on_conflict, dry_run)?;
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&ImportReport { dry_run, decisions })?);
+            return Ok(());
+        }
+
+        for decision in &decisions {
+            match &decision.action {
+                config::ImportAction::Added => println!("➕ {}: added", decision.device),
+                config::ImportAction::Unchanged => println!("🟰 {}: already present, unchanged", decision.device),
+                config::ImportAction::Skipped => println!("⏭️  {}: name taken, kept the existing device", decision.device),
+                config::ImportAction::Overwritten => println!("♻️  {}: replaced the existing device", decision.device),
+                config::ImportAction::Renamed { to } => println!("✏️  {}: name taken, imported as '{}'", decision.device, to),
+            }
+        }
+        if dry_run {
+            println!("Dry run: {} device(s) checked, nothing was written", decisions.len());
+        } else {
+            println!("✅ Imported into {}", manager.config_path().display());
+        }
+
+        Ok(())
+    }
+}
+
+// The part of the config shown by `config edit --devices-only`
+#[derive(Serialize, serde::Deserialize)]
+struct DevicesTable {
+    #[serde(default)]
+    devices: std::collections::HashMap<String, Device>,
+}
+
+fn editor_command() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string())
+}
+
+// Open `path` in the user's editor and wait for it to exit. The editor
+// command goes through the shell so values like "code --wait" work.
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = editor_command();
+    let status = if cfg!(windows) {
+        std::process::Command::new("cmd")
+            .arg("/C")
+            .arg(format!("{} \"{}\"", editor, path.display()))
+            .status()
+    } else {
+        std::process::Command::new("sh")
+            .args(["-c", &format!("{} \"$1\"", editor), "sh"])
+            .arg(path)
+            .status()
+    }
+    .context(format!("Failed to start editor '{}'", editor))?;
+
+    if !status.success() {
+        bail!("Editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+// Parse the edited file back into a complete config, reporting validation
+// errors so the user can fix them
+fn parse_edited(format: ConfigFormat, content: &str, original: &Config, devices_only: bool) -> Result<Config> {
+    let edited = if devices_only {
+        let table: DevicesTable = format.parse(content).context("Failed to parse the edited devices")?;
+        Config { devices: table.devices, ..original.clone() }
+    } else {
+        format.parse(content).context("Failed to parse the edited config")?
+    };
+
+    let issues = config::validate_config(&edited);
+    let errors: Vec<_> = issues
+        .iter()
+        .filter(|issue| issue.severity == config::Severity::Error)
+        .collect();
+    if !errors.is_empty() {
+        for issue in &errors {
+            match &issue.device {
+                Some(device) => eprintln!("❌ [devices.{}] {}", device, issue.problem),
+                None => eprintln!("❌ {}", issue.problem),
+            }
+            eprintln!("   Fix: {}", issue.fix);
+        }
+        bail!("{} error(s) in the edited config", errors.len());
+    }
+
+    Ok(edited)
+}
+
+impl App {
+    pub async fn edit_config(&self, devices_only: bool) -> Result<()> {
+        let manager = self.config()?;
+        // Hold the lock for the whole edit so no other change is lost
+        let _lock = manager.lock()?;
+        let original = manager.load_config()?;
+
+        let format = manager.format();
+        let content = if devices_only {
+            format.serialize(&DevicesTable { devices: original.devices.clone() })?
+        } else {
+            format.serialize(&original)?
+        };
+
+        // Edit a private copy next to the config; it may hold decrypted secrets
+        let temp_path = manager
+            .config_dir()
+            .join(format!(".config-edit-{}.{}", std::process::id(), format.extension()));
+        config::open_private(&temp_path, false)
+            .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()))
+            .context("Failed to create temporary file for editing")?;
+
+        let result = loop {
+            if let Err(err) = run_editor(&temp_path) {
+                break Err(err);
+            }
+            let edited = match std::fs::read_to_string(&temp_path) {
+                Ok(edited) => edited,
+                Err(err) => break Err(err.into()),
+            };
+            if edited == content {
+                break Ok(None);
+            }
+
+            match parse_edited(format, &edited, &original, devices_only) {
+                Ok(config) => break Ok(Some(config)),
+                Err(err) => {
+                    eprintln!("Error: {:#}", err);
+                    if !confirm("Edit again?")? {
+                        break Err(anyhow::anyhow!("Edit aborted; the config was not changed"));
+                    }
+                }
+            }
+        };
+        let _ = std::fs::remove_file(&temp_path);
+
+        match result? {
+            Some(config) => {
+                manager.save_config(&config)?;
+                println!("✅ Saved {}", manager.config_path().display());
+            }
+            None => println!("No changes made"),
+        }
+
+        Ok(())
+    }
+
+    pub async fn migrate_config(&self, to: Option<ConfigFormat>) -> Result<()> {
+        if let Some(format) = to {
+            let config = self.config()?;
+            let converted = config.convert_format(format)?;
+            println!("✅ Converted {} -> {}", config.config_path().display(), converted.display());
+            if config::config_override().is_some() {
+                println!("   Point --config/WRTCLI_CONFIG at the new file");
+            }
+            return Ok(());
+        }
+
+        let migration = config::migrate_legacy_layout()?;
+        for (from, to) in &migration.moved {
+            println!("📦 {} -> {}", from.display(), to.display());
+        }
+        println!("✅ Migrated {} item(s) out of ~/.wrtcli", migration.moved.len());
+        if !migration.left_behind.is_empty() {
+            println!("⚠️  ~/.wrtcli was kept because it still holds:");
+            for path in &migration.left_behind {
+                println!("   {}", path.display());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use crate::config;
+use crate::netrc;
+use crate::models::Device;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::net::IpAddr;
+use super::App;
+
+impl App {
+    pub async fn add_device(
+        &self,
+        name: &str,
+        ip: &str,
+        user: Option<&str>,
+        password: Option<&str>,
+        password_command: Option<&str>,
+        headers: Vec<(String, String)>,
+    ) -> Result<()> {
+        let config = self.config()?;
+        // Credentials left out are looked up in ~/.netrc whenever the device is
+        // used, so nothing from it is stored here
+        let netrc = netrc::lookup(ip);
+        if user.is_none() && netrc.as_ref().is_none_or(|entry| entry.login.is_none()) {
+            bail!("--user is required: ~/.netrc has no login for '{}'", ip);
+        }
+        let password = match (password, password_command) {
+            (Some(password), _) => password.to_string(),
+            (None, Some(_)) => String::new(),
+            (None, None) if netrc.is_some_and(|entry| entry.password.is_some()) => String::new(),
+            // A typed password is never a reference
+            (None, None) => config::literal_credential(&config::read_secret("Password", true)?),
+        };
+        let headers = headers.into_iter().collect();
+        config.add_device(name, ip, user.unwrap_or_default(), &password, password_command, headers)?;
+        println!("✅ Device '{}' added successfully", name);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum DeviceSort {
+    Name,
+    Ip,
+}
+
+// Sort devices for display; addresses that parse as IPs sort numerically
+// ahead of hostnames, which sort alphabetically. Ties fall back to the other
+// fields, so the order never depends on the order in the config.
+pub fn sort_devices(devices: &mut [Device], sort: DeviceSort) {
+    let address = |d: &Device| d.ip.parse::<IpAddr>().map_err(|_| d.ip.clone());
+    match sort {
+        DeviceSort::Name => devices.sort_by_key(|d| (d.name.clone(), address(d), d.user.clone())),
+        DeviceSort::Ip => devices.sort_by_key(|d| (address(d), d.name.clone(), d.user.clone())),
+    }
+}
+
+impl App {
+    pub async fn set_default_device(&self, name: &str) -> Result<()> {
+        let config = self.config()?;
+        config.set_default_device(name)?;
+        println!("✅ Default device set to '{}'", name);
+        Ok(())
+    }
+
+    pub async fn set_device_header(&self, name: &str, key: &str, value: &str) -> Result<()> {
+        self.config()?.set_device_header(name, key, Some(value))?;
+        println!("✅ Header '{}' set for '{}'", key, name);
+        Ok(())
+    }
+
+    pub async fn unset_device_header(&self, name: &str, key: &str) -> Result<()> {
+        self.config()?.set_device_header(name, key, None)?;
+        println!("✅ Header '{}' removed from '{}'", key, name);
+        Ok(())
+    }
+}
+
+impl App {
+    pub async fn list_devices(&self, sort: DeviceSort) -> Result<()> {
+        let config = self.config()?;
+        let mut devices = config.get_all_devices()?;
+        sort_devices(&mut devices, sort);
+
+        if devices.is_empty() {
+            println!("No devices registered. Use 'wrtcli add' to add a device.");
+            return Ok(());
+        }
+
+        println!("Registered OpenWrt devices:");
+        println!("---------------------------");
+        let default_device = config.load_config()?.default_device;
+        for device in &devices {
+            if default_device.as_deref() == Some(device.name.as_str()) {
+                println!("📱 {} ({}) [default]", device.name, device.ip);
+            } else {
+                println!("📱 {} ({})", device.name, device.ip);
+            }
+        }
+        println!("---------------------------");
+        println!(
+            "{} device{}",
+            devices.len(),
+            if devices.len() == 1 { "" } else { "s" }
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(name: &str, ip: &str) -> Device {
+        Device::new(name.to_string(), ip.to_string(), "root".to_string(), String::new())
+    }
+
+    fn order(devices: &[Device]) -> Vec<(&str, &str)> {
+        devices.iter().map(|d| (d.name.as_str(), d.ip.as_str())).collect()
+    }
+
+    #[test]
+    fn sorting_does_not_depend_on_the_input_order() {
+        let devices = vec![
+            device("b", "192.168.1.10"),
+            device("a", "router.lan"),
+            device("c", "192.168.1.9"),
+            device("d", "10.0.0.1"),
+        ];
+        for sort in [DeviceSort::Name, DeviceSort::Ip] {
+            let mut forward = devices.clone();
+            let mut backward: Vec<Device> = devices.iter().rev().cloned().collect();
+            sort_devices(&mut forward, sort);
+            sort_devices(&mut backward, sort);
+            assert_eq!(order(&forward), order(&backward));
+        }
+
+        let mut by_ip = devices.clone();
+        sort_devices(&mut by_ip, DeviceSort::Ip);
+        // Addresses sort numerically, ahead of hostnames
+        assert_eq!(
+            order(&by_ip),
+            vec![("d", "10.0.0.1"), ("c", "192.168.1.9"), ("b", "192.168.1.10"), ("a", "router.lan")]
+        );
+    }
+
+    #[test]
+    fn equal_keys_fall_back_to_the_other_fields() {
+        // Entries whose name fields match (config validate flags this, but
+        // list still shows all of them) sort by address
+        let mut devices = vec![
+            device("router", "10.0.0.10"),
+            device("a", "10.0.0.9"),
+            device("router", "10.0.0.1"),
+            device("router", "10.0.0.2"),
+        ];
+        sort_devices(&mut devices, DeviceSort::Name);
+        assert_eq!(
+            order(&devices),
+            vec![("a", "10.0.0.9"), ("router", "10.0.0.1"), ("router", "10.0.0.2"), ("router", "10.0.0.10")]
+        );
+
+        let mut devices = vec![device("x", "10.0.0.1"), device("x", "10.0.0.1"), device("w", "10.0.0.5")];
+        devices[0].user = "second".to_string();
+        for sort in [DeviceSort::Name, DeviceSort::Ip] {
+            sort_devices(&mut devices, sort);
+            let users: Vec<(&str, &str)> = devices.iter().map(|d| (d.name.as_str(), d.user.as_str())).collect();
+            match sort {
+                DeviceSort::Name => assert_eq!(users, vec![("w", "root"), ("x", "root"), ("x", "second")]),
+                DeviceSort::Ip => assert_eq!(users, vec![("x", "root"), ("x", "second"), ("w", "root")]),
+            }
+        }
+    }
+}
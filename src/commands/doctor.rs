@@ -0,0 +1,255 @@
+use crate::config;
+use crate::models::Device;
+use crate::ubus;
+use anyhow::{bail, Result};
+use reqwest::Client;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use super::App;
+
+#[derive(Serialize)]
+struct DoctorCheck {
+    check: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fix: Option<String>,
+}
+
+#[derive(Default)]
+struct DoctorChecks(Vec<DoctorCheck>);
+
+#[derive(Serialize)]
+struct DoctorReport<'a> {
+    checks: &'a [DoctorCheck],
+    failed: usize,
+}
+
+impl DoctorChecks {
+    fn pass(&mut self, check: impl Into<String>, detail: impl Into<String>) {
+        self.0.push(DoctorCheck { check: check.into(), ok: true, detail: Some(detail.into()), fix: None });
+    }
+
+    fn fail(&mut self, check: impl Into<String>, detail: impl Into<String>, fix: impl Into<String>) {
+        self.0.push(DoctorCheck {
+            check: check.into(),
+            ok: false,
+            detail: Some(detail.into()),
+            fix: Some(fix.into()),
+        });
+    }
+}
+
+async fn tcp_reachable(host: &str, port: u16, connect_timeout: u64) -> Result<()> {
+    tokio::time::timeout(
+        Duration::from_secs(connect_timeout),
+        tokio::net::TcpStream::connect((host, port)),
+    )
+    .await
+    .map_err(|_| anyhow::anyhow!("timed out after {}s", connect_timeout))??;
+    Ok(())
+}
+
+// Whether files can be created in `dir`, or in the nearest existing parent
+// when it hasn't been created yet
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let existing = dir.ancestors().find(|dir| dir.is_dir()).unwrap_or(dir);
+    let probe = existing.join(format!(".doctor-{}", std::process::id()));
+    config::open_private(&probe, false)?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+// The ubus objects every wrtcli feature relies on, with a method to test the
+// ACLs against and the package that provides the object
+const DOCTOR_OBJECTS: &[(&str, &str, &str)] = &[
+    ("session", "access", "rpcd"),
+    ("system", "info", "procd"),
+    ("file", "read", "rpcd-mod-file"),
+];
+
+async fn doctor_device(client: &Client, device: &Device, connect_timeout: u64, checks: &mut DoctorChecks) {
+    let host = device.host();
+    let http_port = device.http_port();
+
+    match tcp_reachable(host, http_port, connect_timeout).await {
+        Ok(()) => checks.pass(format!("HTTP port {} reachable", http_port), host),
+        Err(err) => {
+            checks.fail(
+                format!("HTTP port {} reachable", http_port),
+                format!("{:#}", err),
+                "check the address and that uhttpd is running: /etc/init.d/uhttpd start",
+            );
+            return;
+        }
+    }
+    match tcp_reachable(host, 22, connect_timeout).await {
+        Ok(()) => checks.pass("SSH port 22 reachable", host),
+        Err(err) => checks.fail(
+            "SSH port 22 reachable",
+            format!("{:#}", err),
+            "push/pull need SSH: enable dropbear with /etc/init.d/dropbear start",
+        ),
+    }
+
+    let objects = match ubus::list(client, device, "*").await {
+        Ok(objects) if objects.is_object() => {
+            checks.pass("/ubus answers JSON-RPC", device.ubus_url());
+            objects
+        }
+        Ok(other) => {
+            checks.fail("/ubus answers JSON-RPC", format!("unexpected reply: {}", other), "opkg install uhttpd-mod-ubus && /etc/init.d/uhttpd restart");
+            return;
+        }
+        Err(err) => {
+            checks.fail("/ubus answers JSON-RPC", format!("{:#}", err), "opkg install uhttpd-mod-ubus && /etc/init.d/uhttpd restart");
+            return;
+        }
+    };
+
+    let session = match ubus::login_details(client, device).await {
+        Ok(session) => {
+            checks.pass(format!("Login as '{}'", device.user), "session granted");
+            session
+        }
+        Err(err) => {
+            checks.fail(
+                format!("Login as '{}'", device.user),
+                format!("{:#}", err),
+                "check the device's user and password; non-root users need a 'login' section in /etc/config/rpcd",
+            );
+            return;
+        }
+    };
+
+    for (object, method, package) in DOCTOR_OBJECTS {
+        let check = format!("ubus object '{}'", object);
+        if objects.get(*object).is_none() {
+            checks.fail(check, "not registered on the device", format!("opkg install {}", package));
+        } else if !ubus::acl_allows(&session, object, method) {
+            checks.fail(
+                check,
+                format!("the session may not call {}.{}", object, method),
+                format!("grant '{}' to user '{}' in /usr/share/rpcd/acl.d/ and restart rpcd", object, device.user),
+            );
+        } else {
+            checks.pass(check, "registered and allowed by the ACLs");
+        }
+    }
+
+    let flash_url = format!("http://{}/cgi-bin/luci/admin/system/flash", device.ip);
+    match client.get(&flash_url).send().await {
+        Ok(response) if response.status() != reqwest::StatusCode::NOT_FOUND => {
+            checks.pass("LuCI flash operations page", flash_url)
+        }
+        Ok(response) => checks.fail(
+            "LuCI flash operations page",
+            format!("HTTP {} from {}", response.status(), flash_url),
+            "opkg install luci-mod-system (or the full luci package)",
+        ),
+        Err(err) => checks.fail("LuCI flash operations page", format!("{:#}", err), "opkg install luci"),
+    }
+}
+
+impl App {
+    pub async fn doctor(&self, name: Option<&str>, json_output: bool) -> Result<()> {
+        let mut checks = DoctorChecks::default();
+        let device = match self.config() {
+            Ok(manager) => self.doctor_config(manager, name, &mut checks),
+            Err(err) => {
+                checks.fail("Config directory found", format!("{:#}", err), "check $HOME and the XDG_* variables, or pass --config");
+                None
+            }
+        };
+        if let Some(device) = &device {
+            match self.client(device) {
+                Ok(client) => doctor_device(&client, device, self.options.connect_timeout, &mut checks).await,
+                Err(err) => checks.fail("HTTP client", format!("{:#}", err), "check the --header and timeout settings"),
+            }
+        }
+
+        let failed = checks.0.iter().filter(|check| !check.ok).count();
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&DoctorReport { checks: &checks.0, failed })?);
+        } else {
+            match &device {
+                Some(device) => println!("Doctor: {} ({})", device.name, device.ip),
+                None => match name {
+                    Some(name) => println!("Doctor: {} (device not checked)", name),
+                    None => println!("Doctor (no device selected; pass a name to check one)"),
+                },
+            }
+            println!("----------------");
+            for check in &checks.0 {
+                let detail = check.detail.as_deref().map(|d| format!(" ({})", d)).unwrap_or_default();
+                if check.ok {
+                    println!("✅ {}{}", check.check, detail);
+                } else {
+                    println!("❌ {}{}", check.check, detail);
+                    if let Some(fix) = &check.fix {
+                        println!("   Fix: {}", fix);
+                    }
+                }
+            }
+        }
+
+        if failed > 0 {
+            bail!("{} check(s) failed", failed);
+        }
+        Ok(())
+    }
+
+    // The checks that only need the local config: whether it parses, whether
+    // its directory and the backup directory are writable, and whether the
+    // selected device is in it. A config that doesn't parse leaves the
+    // device unchecked.
+    fn doctor_config(&self, manager: &config::ConfigManager, name: Option<&str>, checks: &mut DoctorChecks) -> Option<Device> {
+        let config = match manager.load_config() {
+            Ok(config) => {
+                checks.pass("Config file parses", manager.config_path().display().to_string());
+                Some(config)
+            }
+            Err(err) => {
+                checks.fail("Config file parses", format!("{:#}", err), "run 'wrtcli config validate' or restore the .bak file");
+                None
+            }
+        };
+
+        // Saving writes a temporary file and the .bak next to the config
+        match probe_writable(manager.config_dir()) {
+            Ok(()) => checks.pass("Config directory writable", manager.config_dir().display().to_string()),
+            Err(err) => checks.fail(
+                "Config directory writable",
+                format!("{}: {}", manager.config_dir().display(), err),
+                "make the directory writable by the current user (the config and its .bak are saved there)",
+            ),
+        }
+        let backups = manager.device_backups_dir(self.options.backup_dir.as_deref());
+        match probe_writable(&backups) {
+            Ok(()) => checks.pass("Backup directory writable", backups.display().to_string()),
+            Err(err) => checks.fail(
+                "Backup directory writable",
+                format!("{}: {}", backups.display(), err),
+                "make it writable by the current user, or point --backup-dir/WRTCLI_BACKUP_DIR elsewhere",
+            ),
+        }
+
+        let config = config?;
+        match name.map(str::to_string).or(config.default_device.clone()) {
+            Some(name) => match config.get_device(&name).cloned().map(config::expand_credentials) {
+                Some(Ok(device)) => Some(device),
+                Some(Err(err)) => {
+                    checks.fail(format!("Device '{}' credentials", name), format!("{:#}", err), "set the variable or fix the credential reference");
+                    None
+                }
+                None => {
+                    checks.fail(format!("Device '{}' configured", name), "not in the config", "add it with 'wrtcli add'");
+                    None
+                }
+            },
+            None => None,
+        }
+    }
+}
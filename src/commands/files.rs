@@ -0,0 +1,68 @@
+use crate::ssh;
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use super::App;
+
+impl App {
+    pub async fn push_file(&self, name: Option<&str>, local: &str, remote: &str) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+
+        let device_name = device.name.clone();
+        let local_path = PathBuf::from(local);
+        let remote_path = remote.to_string();
+        let connect_timeout = self.options.connect_timeout;
+
+        let size = tokio::task::spawn_blocking(move || -> Result<u64> {
+            let mut file = File::open(&local_path)
+                .context(format!("Failed to open '{}'", local_path.display()))?;
+            let metadata = file.metadata()?;
+            if !metadata.is_file() {
+                bail!("'{}' is not a regular file", local_path.display());
+            }
+
+            #[cfg(unix)]
+            let mode = {
+                use std::os::unix::fs::PermissionsExt;
+                (metadata.permissions().mode() & 0o777) as i32
+            };
+            #[cfg(not(unix))]
+            let mode = 0o644;
+
+            let session = ssh::ssh_connect(&device, connect_timeout)?;
+            ssh::scp_upload(&session, &mut file, &remote_path, mode)
+        })
+        .await??;
+
+        println!("✅ Pushed {} ({} bytes) to {}:{}", local, size, device_name, remote);
+        Ok(())
+    }
+
+    pub async fn pull_file(&self, name: Option<&str>, remote: &str, local: &str) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+
+        let device_name = device.name.clone();
+        let remote_path = remote.to_string();
+        let connect_timeout = self.options.connect_timeout;
+        let mut local_path = PathBuf::from(local);
+        if local_path.is_dir() {
+            let file_name = Path::new(remote)
+                .file_name()
+                .context(format!("'{}' does not name a file", remote))?;
+            local_path.push(file_name);
+        }
+
+        let (size, local_path) = tokio::task::spawn_blocking(move || -> Result<(u64, PathBuf)> {
+            let session = ssh::ssh_connect(&device, connect_timeout)?;
+            let copied = ssh::scp_download(&session, &remote_path, &local_path)?;
+
+            Ok((copied, local_path))
+        })
+        .await??;
+
+        println!("✅ Pulled {}:{} ({} bytes) to {}", device_name, remote, size, local_path.display());
+        Ok(())
+    }
+}
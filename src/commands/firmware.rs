@@ -0,0 +1,394 @@
+use crate::config;
+use crate::firmware;
+use crate::models::Device;
+use crate::ssh;
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde_json::json;
+use serde::Serialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::status::{fetch_status, format_uptime};
+use super::system::wait_for_reboot;
+use super::{confirm, run_quiet, AggregateResult, App};
+
+// Firmware release running on a device, from `system board`
+struct DeviceRelease {
+    version: String,
+    target: String,
+    board_name: String,
+}
+
+#[derive(Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum FirmwareState {
+    UpToDate,
+    UpdateAvailable,
+    // A newer release exists but has no image for this board
+    Unsupported,
+    // The running version can't be compared, e.g. a snapshot build
+    Unknown,
+}
+
+#[derive(Serialize)]
+struct FirmwareReport {
+    device_name: String,
+    current_version: String,
+    target: String,
+    board_name: String,
+    latest_version: String,
+    state: FirmwareState,
+    // The latest release's sysupgrade image, when an update is available
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<firmware::ReleaseImage>,
+}
+
+async fn fetch_release(client: &Client, device: &Device) -> Result<DeviceRelease> {
+    let session = ubus::login(client, device).await?;
+    let board_info = ubus::call(client, device, &session, "system", "board", json!({})).await?;
+
+    let release = &board_info["release"];
+    Ok(DeviceRelease {
+        version: release["version"]
+            .as_str()
+            .context("Device did not report its firmware version")?
+            .to_string(),
+        target: release["target"]
+            .as_str()
+            .context("Device did not report its firmware target")?
+            .to_string(),
+        board_name: board_info["board_name"].as_str().unwrap_or("Unknown").to_string(),
+    })
+}
+
+async fn firmware_report(
+    feed: &firmware::Feed,
+    latest: &str,
+    device_name: &str,
+    release: DeviceRelease,
+) -> Result<FirmwareReport> {
+    let (state, image) = match firmware::is_newer(latest, &release.version) {
+        None => (FirmwareState::Unknown, None),
+        Some(false) => (FirmwareState::UpToDate, None),
+        Some(true) => {
+            let board = Some(release.board_name.as_str()).filter(|b| *b != "Unknown");
+            match feed.target_support(latest, &release.target, board).await? {
+                firmware::TargetSupport::Supported(image) => (FirmwareState::UpdateAvailable, image),
+                firmware::TargetSupport::Unsupported => (FirmwareState::Unsupported, None),
+            }
+        }
+    };
+
+    Ok(FirmwareReport {
+        device_name: device_name.to_string(),
+        current_version: release.version,
+        target: release.target,
+        board_name: release.board_name,
+        latest_version: latest.to_string(),
+        state,
+        image,
+    })
+}
+
+fn firmware_state_text(report: &FirmwareReport) -> String {
+    match report.state {
+        FirmwareState::UpToDate => "✅ Up to date".to_string(),
+        FirmwareState::UpdateAvailable => format!("⬆️  Update available: {}", report.latest_version),
+        FirmwareState::Unsupported => format!(
+            "⚠️  {} has no image for board {}",
+            report.latest_version, report.board_name
+        ),
+        FirmwareState::Unknown => format!(
+            "❓ Can't compare {} with {}",
+            report.current_version, report.latest_version
+        ),
+    }
+}
+
+impl App {
+    pub async fn check_firmware(&self, name: Option<&str>, feed_url: &str, json_output: bool) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let release = fetch_release(&self.client(&device)?, &device).await?;
+
+        let feed = firmware::Feed::new(feed_url, config.cache_dir())?;
+        let latest = feed.latest_stable().await?;
+        let report = firmware_report(&feed, &latest, &device.name, release).await?;
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+            return Ok(());
+        }
+
+        println!("Firmware: {}", report.device_name);
+        println!("----------------");
+        println!("📦 Current: {} ({})", report.current_version, report.target);
+        println!("🏷️  Board: {}", report.board_name);
+        println!("🌐 Latest stable: {}", report.latest_version);
+        println!("{}", firmware_state_text(&report));
+        if let Some(image) = &report.image {
+            println!("🔗 Image: {}", image.url);
+            println!("🔒 SHA-256: {}", image.sha256);
+        }
+
+        Ok(())
+    }
+
+    pub async fn check_firmware_all(
+        &self,
+        feed_url: &str,
+        json_output: bool,
+        fail_fast: bool,
+    ) -> Result<()> {
+        let config = self.config()?;
+        let feed = firmware::Feed::new(feed_url, config.cache_dir())?;
+        let latest = feed.latest_stable().await?;
+
+        let releases = self
+            .run_on_all_devices(fail_fast, |client, device| async move { fetch_release(&client, &device).await })
+            .await?;
+
+        // Check targets one at a time so that devices sharing a target reuse the
+        // profile list fetched for the first one instead of all fetching it at once
+        let mut results = Vec::new();
+        for (name, release) in releases {
+            let report = match release {
+                Ok(release) => firmware_report(&feed, &latest, &name, release).await,
+                Err(err) => Err(err),
+            };
+            results.push((name, report));
+        }
+        let total = results.len();
+        let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&AggregateResult::new(results))?);
+        } else {
+            println!("Latest stable release: {}", latest);
+            println!();
+            let column = |header: &str, value: fn(&FirmwareReport) -> &str| {
+                results
+                    .iter()
+                    .filter_map(|(_, result)| result.as_ref().ok().map(|report| value(report).len()))
+                    .max()
+                    .unwrap_or(0)
+                    .max(header.len())
+            };
+            let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("DEVICE".len());
+            let version_width = column("CURRENT", |report| &report.current_version);
+            let target_width = column("TARGET", |report| &report.target);
+            println!("{:<width$}  {:<version_width$}  {:<target_width$}  STATUS", "DEVICE", "CURRENT", "TARGET");
+            for (name, result) in &results {
+                match result {
+                    Ok(report) => println!(
+                        "{:<width$}  {:<version_width$}  {:<target_width$}  {}",
+                        name,
+                        report.current_version,
+                        report.target,
+                        firmware_state_text(report)
+                    ),
+                    Err(err) => println!("{:<width$}  {:<version_width$}  {:<target_width$}  ❌ {:#}", name, "-", "-", err),
+                }
+            }
+            let outdated = results
+                .iter()
+                .filter(|(_, result)| matches!(result, Ok(report) if report.state == FirmwareState::UpdateAvailable))
+                .count();
+            println!();
+            println!("{} of {} device(s) can be upgraded", outdated, total);
+            if outdated > 0 {
+                println!("Run 'wrtcli firmware check <name>' for a device's image URL and checksum");
+            }
+        }
+
+        if failed == total {
+            bail!("Failed to check firmware on all {} devices", total);
+        }
+
+        Ok(())
+    }
+}
+
+const SYSUPGRADE_IMAGE: &str = "/tmp/wrtcli-sysupgrade.bin";
+const SYSUPGRADE_BACKUP: &str = "/tmp/wrtcli-backup.tar.gz";
+
+// What `wrtcli sysupgrade` should do, from its command line
+pub struct SysupgradeOptions {
+    pub image: PathBuf,
+    pub sha256: Option<String>,
+    pub sums: Option<PathBuf>,
+    pub keep_config: bool,
+    pub backup: bool,
+    pub wait: bool,
+    pub timeout: u64,
+    pub yes: bool,
+}
+
+// The checksum given with --sha256, or the one listed for the image in a
+// sha256sums file ("<hash>  <file>" or "<hash> *<file>")
+fn expected_sha256(image: &Path, sha256: Option<&str>, sums: Option<&Path>) -> Result<String> {
+    let hash = match (sha256, sums) {
+        (Some(sha256), _) => sha256.trim().to_string(),
+        (None, Some(sums)) => {
+            let file_name = image.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            let content = std::fs::read_to_string(sums).context(format!("Failed to read {}", sums.display()))?;
+            content
+                .lines()
+                .filter_map(|line| line.split_once(char::is_whitespace))
+                .find(|(_, name)| name.trim().trim_start_matches('*') == file_name)
+                .map(|(hash, _)| hash.to_string())
+                .context(format!("{} has no checksum for {}", sums.display(), file_name))?
+        }
+        (None, None) => bail!("Pass --sha256 or --sums so the image can be verified before flashing"),
+    };
+
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("'{}' is not a sha256 checksum", hash);
+    }
+    Ok(hash.to_lowercase())
+}
+
+// Ask before a step of the upgrade, unless --yes was given; declining stops
+// the upgrade
+fn confirm_step(yes: bool, question: &str) -> Result<()> {
+    if yes || confirm(question)? {
+        return Ok(());
+    }
+    bail!("Aborted; the device was not flashed")
+}
+
+// Check the uploaded image against the checksum and the device's board, and
+// take the configuration backup. Nothing here changes the device itself.
+fn prepare_flash(
+    session: &ssh2::Session,
+    sha256: &str,
+    board_name: &str,
+    backup_path: Option<&Path>,
+    yes: bool,
+) -> Result<()> {
+    let sums = run_quiet(session, &format!("sha256sum {}", SYSUPGRADE_IMAGE), None)?;
+    let actual = sums.split_whitespace().next().unwrap_or_default();
+    if !actual.eq_ignore_ascii_case(sha256) {
+        bail!("Checksum mismatch: expected {}, the uploaded image has {}", sha256, actual);
+    }
+    println!("✅ Checksum matches");
+
+    // fwtool prints the metadata the build system embeds in the image;
+    // images without it are left to `sysupgrade --test`
+    let metadata = run_quiet(session, &format!("fwtool -q -i /dev/stdout {}", SYSUPGRADE_IMAGE), None)
+        .ok()
+        .and_then(|metadata| serde_json::from_str::<serde_json::Value>(&metadata).ok());
+    match metadata {
+        Some(metadata) => {
+            let supported: Vec<&str> = metadata["supported_devices"]
+                .as_array()
+                .map(|devices| devices.iter().filter_map(|d| d.as_str()).collect())
+                .unwrap_or_default();
+            if !supported.contains(&board_name) {
+                bail!("The image is for {}, not this device's board '{}'", supported.join(", "), board_name);
+            }
+            println!("✅ Image supports board {}", board_name);
+        }
+        None => println!("⚠️  The image has no metadata; relying on sysupgrade --test"),
+    }
+    run_quiet(session, &format!("sysupgrade --test {}", SYSUPGRADE_IMAGE), None)
+        .context("sysupgrade --test rejected the image")?;
+    println!("✅ sysupgrade --test accepted the image");
+
+    if let Some(path) = backup_path {
+        confirm_step(yes, "Download a backup of the device's configuration?")?;
+        run_quiet(session, &format!("sysupgrade -b {}", SYSUPGRADE_BACKUP), None)?;
+        ssh::scp_download(session, SYSUPGRADE_BACKUP, path)?;
+        config::set_private_mode(path, 0o600)?;
+        println!("💾 Saved configuration backup to {}", path.display());
+    }
+
+    Ok(())
+}
+
+// Upload, verify and flash the image. Until the final step only files in
+// the device's /tmp are written, and they are removed again on failure.
+fn flash_image(
+    device: &Device,
+    connect_timeout: u64,
+    upgrade: &SysupgradeOptions,
+    sha256: &str,
+    board_name: &str,
+    backup_path: Option<&Path>,
+) -> Result<()> {
+    let (image, keep_config, yes) = (&upgrade.image, upgrade.keep_config, upgrade.yes);
+    let mut file = File::open(image).context(format!("Failed to open '{}'", image.display()))?;
+    let session = ssh::ssh_connect(device, connect_timeout)?;
+
+    confirm_step(yes, &format!("Upload {} to '{}'?", image.display(), device.name))?;
+    if let Err(err) = ssh::scp_upload(&session, &mut file, SYSUPGRADE_IMAGE, 0o600)
+        .and_then(|_| prepare_flash(&session, sha256, board_name, backup_path, yes))
+        .and_then(|_| {
+            let settings = if keep_config { "keeping its settings" } else { "ERASING its settings" };
+            confirm_step(yes, &format!("Flash '{}' now, {}? It reboots when done", device.name, settings))
+        })
+    {
+        let cleanup = format!("rm -f {} {}", SYSUPGRADE_IMAGE, SYSUPGRADE_BACKUP);
+        let _ = run_quiet(&session, &cleanup, None);
+        return Err(err);
+    }
+
+    // sysupgrade ends every session while it flashes, so it runs detached
+    let flags = if keep_config { "" } else { "-n " };
+    let command = format!(
+        "rm -f {}; nohup sysupgrade {}{} >/dev/null 2>&1 &",
+        SYSUPGRADE_BACKUP, flags, SYSUPGRADE_IMAGE
+    );
+    run_quiet(&session, &command, Some(Duration::from_secs(10)))?;
+    Ok(())
+}
+
+impl App {
+    pub async fn sysupgrade(&self, name: Option<&str>, upgrade: SysupgradeOptions) -> Result<()> {
+        if !upgrade.image.is_file() {
+            bail!("'{}' is not a regular file", upgrade.image.display());
+        }
+        let sha256 = expected_sha256(&upgrade.image, upgrade.sha256.as_deref(), upgrade.sums.as_deref())?;
+
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let release = fetch_release(&client, &device).await?;
+        let uptime_before = fetch_status(&client, &device).await?.uptime;
+        let backup_path = if upgrade.backup { Some(config.new_device_backup_path(self.options.backup_dir.as_deref(), &device.name, "sysupgrade", "tar.gz")?) } else { None };
+        println!(
+            "📦 '{}' runs {} ({}, board {})",
+            device.name, release.version, release.target, release.board_name
+        );
+
+        let connect_timeout = self.options.connect_timeout;
+        let (wait, timeout) = (upgrade.wait, upgrade.timeout);
+        let flashed = {
+            let device = device.clone();
+            tokio::task::spawn_blocking(move || {
+                let board_name = &release.board_name;
+                flash_image(&device, connect_timeout, &upgrade, &sha256, board_name, backup_path.as_deref())
+            })
+        };
+        flashed.await??;
+        let requested_at = Instant::now();
+        println!("⚡ Flashing '{}'; do not power it off until it is back", device.name);
+
+        if wait {
+            println!("⏳ Waiting up to {} seconds for the device to come back...", timeout);
+            let downtime =
+                wait_for_reboot(&client, &device, uptime_before, requested_at, Duration::from_secs(timeout)).await?;
+            let release = fetch_release(&client, &device).await?;
+            println!(
+                "✅ '{}' is back online after {} and runs {}",
+                device.name,
+                format_uptime(downtime.as_secs()),
+                release.version
+            );
+        }
+
+        Ok(())
+    }
+}
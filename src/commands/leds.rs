@@ -0,0 +1,72 @@
+use crate::leds;
+use crate::ubus;
+use anyhow::Result;
+use serde::Serialize;
+use super::App;
+
+#[derive(Serialize)]
+struct LedList {
+    device_name: String,
+    leds: Vec<leds::Led>,
+}
+
+impl App {
+    pub async fn list_leds(&self, name: Option<&str>, json_output: bool) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+        let list = LedList { leds: leds::list(&client, &device, &session).await?, device_name: device.name };
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&list)?);
+            return Ok(());
+        }
+        if list.leds.is_empty() {
+            println!("'{}' has no LEDs in /sys/class/leds", list.device_name);
+            return Ok(());
+        }
+
+        let width = list.leds.iter().map(|led| led.name.len()).max().unwrap_or(0).max("LED".len());
+        let trigger_width = list.leds.iter().map(|led| led.trigger.len()).max().unwrap_or(0).max("TRIGGER".len());
+        println!("{:<width$}  {:<trigger_width$}  BRIGHTNESS", "LED", "TRIGGER");
+        for led in &list.leds {
+            println!(
+                "{:<width$}  {:<trigger_width$}  {}/{}",
+                led.name, led.trigger, led.brightness, led.max_brightness
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn set_led(
+        &self,
+        name: Option<&str>,
+        led_name: &str,
+        trigger: Option<&str>,
+        brightness: Option<u32>,
+        persist: bool,
+    ) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+
+        let all = leds::list(&client, &device, &session).await?;
+        let led = leds::find(&device, &all, led_name)?;
+        leds::set(&client, &device, &session, led, trigger, brightness).await?;
+
+        // Read the LED back to report what the kernel applied
+        let all = leds::list(&client, &device, &session).await?;
+        let led = leds::find(&device, &all, led_name)?;
+        println!("💡 {} on '{}': trigger {}, brightness {}/{}", led.name, device.name, led.trigger, led.brightness, led.max_brightness);
+
+        if persist {
+            let steady = led.trigger == "none" || led.trigger == "default-on";
+            let saved_brightness = brightness.or(steady.then_some(led.brightness));
+            leds::persist(&client, &device, &session, led, saved_brightness).await?;
+            println!("💾 Saved in /etc/config/system, so it is applied at boot");
+        }
+        Ok(())
+    }
+}
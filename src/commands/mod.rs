@@ -0,0 +1,336 @@
+use crate::config::{self, ConfigManager};
+use crate::models::Device;
+use crate::ssh;
+use crate::ubus::{ClientFactory, ClientOptions};
+use anyhow::{bail, Result};
+use reqwest::Client;
+use serde::Serialize;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+mod config_file;
+mod devices;
+mod doctor;
+mod files;
+mod firmware;
+mod leds;
+mod monitor;
+mod network;
+mod packages;
+mod profiles;
+mod status;
+mod system;
+mod ubus;
+mod wifi;
+
+pub use devices::DeviceSort;
+pub use firmware::SysupgradeOptions;
+pub use status::StatusFormat;
+pub use wifi::WifiUpdate;
+
+use devices::sort_devices;
+
+// Result of running a command against one device as part of `--all`
+#[derive(Serialize)]
+struct DeviceResult<T: Serialize> {
+    device: String,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Aggregate JSON document emitted by every `--all` command
+#[derive(Serialize)]
+struct AggregateResult<T: Serialize> {
+    results: Vec<DeviceResult<T>>,
+    succeeded: usize,
+    failed: usize,
+}
+
+impl<T: Serialize> AggregateResult<T> {
+    fn new(results: Vec<(String, Result<T>)>) -> Self {
+        let results: Vec<DeviceResult<T>> = results
+            .into_iter()
+            .map(|(device, result)| match result {
+                Ok(data) => DeviceResult { device, ok: true, data: Some(data), error: None },
+                Err(err) => DeviceResult {
+                    device,
+                    ok: false,
+                    data: None,
+                    error: Some(format!("{:#}", err)),
+                },
+            })
+            .collect();
+        let failed = results.iter().filter(|r| !r.ok).count();
+
+        Self {
+            succeeded: results.len() - failed,
+            failed,
+            results,
+        }
+    }
+}
+
+// State shared by everything a single command invocation does: the config
+// (opened once) and the HTTP clients, so that `--all` commands reuse them
+// for every device
+pub struct App {
+    config: OnceLock<ConfigManager>,
+    options: ClientOptions,
+    clients: ClientFactory,
+}
+
+impl App {
+    // `config` is `None` when it couldn't be opened up front; the first
+    // command that needs it then reports why
+    pub fn new(config: Option<ConfigManager>, options: ClientOptions) -> Self {
+        let clients = ClientFactory::new(&options);
+        Self::with_clients(config, options, clients)
+    }
+
+    // Like `new`, with the HTTP clients handed out by `clients`, e.g. ones
+    // that talk to a fake device in tests
+    pub fn with_clients(config: Option<ConfigManager>, options: ClientOptions, clients: ClientFactory) -> Self {
+        let cell = OnceLock::new();
+        if let Some(config) = config {
+            let _ = cell.set(config);
+        }
+        Self { config: cell, options, clients }
+    }
+
+    pub fn options(&self) -> &ClientOptions {
+        &self.options
+    }
+
+    fn config(&self) -> Result<&ConfigManager> {
+        if let Some(config) = self.config.get() {
+            return Ok(config);
+        }
+        let config = ConfigManager::new()?;
+        Ok(self.config.get_or_init(|| config))
+    }
+
+    fn client(&self, device: &Device) -> Result<Client> {
+        self.clients.client(device)
+    }
+
+    // Run `task` against every registered device concurrently, returning the
+    // per-device results in device name order. With `fail_fast` the first
+    // error aborts the remaining tasks and is returned instead.
+    async fn run_on_all_devices<T, F, Fut>(&self, fail_fast: bool, task: F) -> Result<Vec<(String, Result<T>)>>
+    where
+        T: Send + 'static,
+        F: Fn(Client, Device) -> Fut,
+        Fut: std::future::Future<Output = Result<T>> + Send + 'static,
+    {
+        let mut devices = self.config()?.get_all_devices()?;
+        if devices.is_empty() {
+            bail!("No devices registered. Use 'wrtcli add' to add a device.");
+        }
+        sort_devices(&mut devices, DeviceSort::Name);
+
+        let semaphore = Arc::new(Semaphore::new(self.options.concurrency));
+        let mut tasks = JoinSet::new();
+        for (index, device) in devices.iter().enumerate() {
+            let semaphore = semaphore.clone();
+            // Credential and header errors are reported for the device like
+            // any other failure
+            let future = config::expand_credentials(device.clone())
+                .and_then(|device| Ok(task(self.client(&device)?, device)));
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = match future {
+                    Ok(future) => future.await,
+                    Err(err) => Err(err),
+                };
+                (index, result)
+            });
+        }
+
+        let mut results: Vec<Option<Result<T>>> = devices.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            let (index, result) = joined?;
+            if fail_fast {
+                if let Err(err) = result {
+                    tasks.abort_all();
+                    return Err(err.context(format!("Device '{}' failed", devices[index].name)));
+                }
+            }
+            results[index] = Some(result);
+        }
+
+        Ok(devices
+            .into_iter()
+            .zip(results)
+            .map(|(device, result)| (device.name, result.expect("every task reports a result")))
+            .collect())
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    use std::io::Write;
+    print!("{} [Y/n] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    // Treat end of input as "no" so a non-interactive run can't loop
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        return Ok(false);
+    }
+    Ok(!answer.trim().to_lowercase().starts_with('n'))
+}
+
+// Run a command over SSH without showing its output, which is returned. A
+// non-zero exit status fails with the output as the reason.
+fn run_quiet(session: &ssh2::Session, command: &str, timeout: Option<Duration>) -> Result<String> {
+    let mut output = Vec::new();
+    let code = ssh::run_command(session, command, timeout, &mut output)?;
+    let output = String::from_utf8_lossy(&output).into_owned();
+    if code != 0 {
+        bail!("'{}' exited with status {}: {}", command, code, output.trim());
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Config, OutputFormat};
+    use serde_json::{json, Value};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    // What rpcd on a fake device replies to a ubus call. Devices whose
+    // address ends in .99 reject every login.
+    fn fake_reply(target: &str, request: &Value) -> Value {
+        let params = &request["params"];
+        let result = match (params[1].as_str().unwrap(), params[2].as_str().unwrap()) {
+            _ if target.contains(".99/") => json!([6]),
+            ("session", "login") => json!([0, { "ubus_rpc_session": format!("session for {}", target), "timeout": 300 }]),
+            ("system", "board") => json!([0, { "model": "Fake Router", "hostname": "OpenWrt" }]),
+            ("system", "info") => json!([0, { "uptime": 3600, "load": [0, 0, 0], "memory": { "total": 131072, "free": 65536 } }]),
+            ("uci", "configs") => json!([0, { "configs": ["network", "wireless"] }]),
+            ("uci", "get") if params[3]["type"] == "wifi-iface" => json!([0, {
+                "values": { "default_radio0": { "device": "radio0", "ssid": "OpenWrt", "encryption": "psk2" } }
+            }]),
+            _ => json!([3]),
+        };
+        json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+    }
+
+    // An HTTP proxy on a local port that answers for every device itself,
+    // returning its URL. Proxied requests name the device they are for
+    // ("POST http://192.0.2.10/ubus HTTP/1.1").
+    fn fake_devices() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let target = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let reply = fake_reply(target, &serde_json::from_slice(&body).unwrap()).to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    reply.len(),
+                    reply
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    // An App over a scratch config with `devices` (name, address), talking
+    // to the fake devices. Device addresses are cached process-wide with
+    // their sessions, so every test uses its own.
+    fn app(name: &str, devices: &[(&str, &str)]) -> App {
+        let dir = std::env::temp_dir().join(format!("wrtcli-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ConfigManager::from_path(dir.join("config.toml")).unwrap();
+        let mut contents = Config::new();
+        for (name, ip) in devices {
+            contents.add_device(Device::new(name.to_string(), ip.to_string(), "root".into(), "secret".into()));
+        }
+        config.save_config(&contents).unwrap();
+
+        let options = ClientOptions {
+            connect_timeout: 3,
+            headers: Vec::new(),
+            concurrency: 2,
+            output: OutputFormat::Text,
+            backup_dir: None,
+            log_level: None,
+            log_file: None,
+            sources: Vec::new(),
+        };
+        let clients = ClientFactory::with_proxy(&options, &fake_devices());
+        App::with_clients(Some(config), options, clients)
+    }
+
+    #[tokio::test]
+    async fn all_device_commands_report_every_device_in_name_order() {
+        let app = app("all-devices", &[("gw", "192.0.2.10"), ("ap", "192.0.2.99"), ("core", "192.0.2.11")]);
+        let uptime = |client: Client, device: Device| async move {
+            Ok::<_, anyhow::Error>(status::fetch_status(&client, &device).await?.uptime)
+        };
+
+        let results = app.run_on_all_devices(false, uptime).await.unwrap();
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["ap", "core", "gw"]);
+        let err = results[0].1.as_ref().unwrap_err();
+        assert!(format!("{:#}", err).contains("permission denied"), "{:#}", err);
+        assert_eq!(*results[1].1.as_ref().unwrap(), 3600);
+        assert_eq!(*results[2].1.as_ref().unwrap(), 3600);
+
+        let err = app.run_on_all_devices(true, uptime).await.unwrap_err();
+        assert_eq!(err.to_string(), "Device 'ap' failed");
+    }
+
+    #[tokio::test]
+    async fn recorded_status_lands_in_the_history() {
+        let app = app("record-status", &[("gw", "192.0.2.12")]);
+        app.get_status(Some("gw"), false, StatusFormat::Json, true).await.unwrap();
+
+        let history = app.config().unwrap().load_history("gw").unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].uptime, 3600);
+        assert_eq!((history[0].memory_total_kb, history[0].memory_free_kb), (131072, 65536));
+    }
+
+    #[tokio::test]
+    async fn wifi_set_all_skips_devices_without_the_network() {
+        let mixed = app("wifi-set-skip", &[("gw", "192.0.2.13"), ("ap", "192.0.2.99")]);
+        let update = WifiUpdate { target: "Guest".into(), new_ssid: Some("Visitors".into()), new_password: None };
+
+        let err = mixed.set_wifi_network(None, true, false, true, update).await.unwrap_err();
+        assert_eq!(err.to_string(), "Failed to update 1 of 2 devices (1 skipped)");
+
+        let gw_only = app("wifi-set-none", &[("gw", "192.0.2.14")]);
+        let update = WifiUpdate { target: "Guest".into(), new_ssid: Some("Visitors".into()), new_password: None };
+        let err = gw_only.set_wifi_network(None, true, false, false, update).await.unwrap_err();
+        assert_eq!(err.to_string(), "No device has a wifi network 'Guest'");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::models::Device;
+use crate::ubus;
+use anyhow::{Context, Result};
+use reqwest::Client;
+use serde_json::json;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use super::App;
+
+#[derive(Serialize)]
+struct TrafficSample {
+    timestamp: u64,
+    interface: String,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_bytes_per_sec: f64,
+    tx_bytes_per_sec: f64,
+}
+
+// Helper function to format a byte count into a human readable format
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+// Resolve a logical interface (e.g. "wan") to its layer 3 device, falling
+// back to treating the name as a device name (e.g. "eth0") when it isn't one
+async fn resolve_network_device(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    iface: &str,
+) -> String {
+    let object = format!("network.interface.{}", iface);
+    match ubus::call(client, device, session, &object, "status", json!({})).await {
+        Ok(status) => status["l3_device"]
+            .as_str()
+            .or_else(|| status["device"].as_str())
+            .unwrap_or(iface)
+            .to_string(),
+        Err(_) => iface.to_string(),
+    }
+}
+
+impl App {
+    pub async fn monitor_traffic(
+        &self,
+        name: Option<&str>,
+        iface: &str,
+        interval: u64,
+        json_output: bool,
+    ) -> Result<()> {
+        const ROLLING_WINDOW: usize = 5;
+
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+        let netdev = resolve_network_device(&client, &device, &session, iface).await;
+
+        if !json_output {
+            println!("Monitoring {} on '{}' every {}s (Ctrl-C to stop)", netdev, device.name, interval);
+            println!("----------------");
+        }
+
+        let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+        let mut previous: Option<(Instant, u64, u64)> = None;
+        let mut window: VecDeque<(f64, f64)> = VecDeque::new();
+
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => break,
+                _ = ticker.tick() => {}
+            }
+
+            let status = ubus::call(
+                &client,
+                &device,
+                &session,
+                "network.device",
+                "status",
+                json!({ "name": netdev }),
+            )
+            .await
+            .context(format!("Failed to read statistics for '{}'", netdev))?;
+
+            let now = Instant::now();
+            let rx_bytes = status["statistics"]["rx_bytes"].as_u64().unwrap_or(0);
+            let tx_bytes = status["statistics"]["tx_bytes"].as_u64().unwrap_or(0);
+
+            if let Some((last_time, last_rx, last_tx)) = previous {
+                let elapsed = now.duration_since(last_time).as_secs_f64();
+                let rx_rate = rx_bytes.saturating_sub(last_rx) as f64 / elapsed;
+                let tx_rate = tx_bytes.saturating_sub(last_tx) as f64 / elapsed;
+
+                if json_output {
+                    let sample = TrafficSample {
+                        timestamp: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)?
+                            .as_secs(),
+                        interface: netdev.clone(),
+                        rx_bytes,
+                        tx_bytes,
+                        rx_bytes_per_sec: rx_rate,
+                        tx_bytes_per_sec: tx_rate,
+                    };
+                    println!("{}", serde_json::to_string(&sample)?);
+                } else {
+                    window.push_back((rx_rate, tx_rate));
+                    if window.len() > ROLLING_WINDOW {
+                        window.pop_front();
+                    }
+                    let avg_rx = window.iter().map(|(rx, _)| rx).sum::<f64>() / window.len() as f64;
+                    let avg_tx = window.iter().map(|(_, tx)| tx).sum::<f64>() / window.len() as f64;
+
+                    println!(
+                        "⬇️  RX: {:>10}/s (avg {:>10}/s)  ⬆️  TX: {:>10}/s (avg {:>10}/s)  Total: {} / {}",
+                        format_bytes(rx_rate),
+                        format_bytes(avg_rx),
+                        format_bytes(tx_rate),
+                        format_bytes(avg_tx),
+                        format_bytes(rx_bytes as f64),
+                        format_bytes(tx_bytes as f64),
+                    );
+                }
+            }
+
+            previous = Some((now, rx_bytes, tx_bytes));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,350 @@
+use crate::config;
+use crate::models::{split_host_port, Device};
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde_json::{json, Value};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use super::{confirm, App};
+
+const INTERFACE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// The `network.interface dump` entry for `iface`
+async fn interface_entry(client: &Client, device: &Device, session: &str, iface: &str) -> Result<Option<Value>> {
+    let dump = ubus::call(client, device, session, "network.interface", "dump", json!({})).await?;
+    Ok(dump["interface"]
+        .as_array()
+        .and_then(|interfaces| interfaces.iter().find(|entry| entry["interface"].as_str() == Some(iface)))
+        .cloned())
+}
+
+// An interface's addresses as (address, prefix length) pairs
+fn interface_addresses(entry: &Value) -> Vec<(IpAddr, u8)> {
+    ["ipv4-address", "ipv6-address"]
+        .iter()
+        .filter_map(|key| entry[*key].as_array())
+        .flatten()
+        .filter_map(|address| {
+            let ip = address["address"].as_str()?.parse().ok()?;
+            Some((ip, address["mask"].as_u64()? as u8))
+        })
+        .collect()
+}
+
+fn in_subnet(address: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (address, network) {
+        (IpAddr::V4(address), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(prefix.min(32))).unwrap_or(0);
+            u32::from(address) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(address), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(prefix.min(128))).unwrap_or(0);
+            u128::from(address) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+// The address wrtcli uses to reach the device (the device's own, or this
+// machine's) when it lies in one of the interface's subnets
+fn reaches_device_over(device: &Device, entry: &Value) -> Result<Option<IpAddr>> {
+    let (remote, local) = ubus::route_to(device)?;
+    let addresses = interface_addresses(entry);
+    Ok([remote, local]
+        .into_iter()
+        .find(|ip| addresses.iter().any(|(network, prefix)| in_subnet(*ip, *network, *prefix))))
+}
+
+fn format_addresses(entry: &Value) -> String {
+    let addresses: Vec<String> = interface_addresses(entry)
+        .iter()
+        .map(|(ip, prefix)| format!("{}/{}", ip, prefix))
+        .collect();
+    if addresses.is_empty() {
+        "no address".to_string()
+    } else {
+        addresses.join(", ")
+    }
+}
+
+#[derive(Serialize)]
+struct InterfaceResult {
+    device_name: String,
+    interface: String,
+    up: bool,
+    // The interface's final `network.interface dump` entry
+    status: Value,
+}
+
+impl App {
+    pub async fn set_interface(
+        &self,
+        name: Option<&str>,
+        iface: &str,
+        up: bool,
+        timeout: u64,
+        force: bool,
+        json_output: bool,
+    ) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+
+        let Some(entry) = interface_entry(&client, &device, &session, iface).await? else {
+            bail!("'{}' has no interface '{}'", device.name, iface);
+        };
+        // Only go ahead when the route to the device is known to avoid the
+        // interface
+        if !up && !force {
+            match reaches_device_over(&device, &entry) {
+                Ok(None) => {}
+                Ok(Some(address)) => bail!(
+                    "Refusing to take down '{}': wrtcli reaches '{}' through it ({} is in its subnet), so the device \
+                     would become unreachable. Pass --yes-i-know to do it anyway",
+                    iface,
+                    device.name,
+                    address
+                ),
+                Err(err) => bail!(
+                    "Refusing to take down '{}': could not tell whether wrtcli reaches '{}' through it ({:#}). \
+                     Pass --yes-i-know to do it anyway",
+                    iface,
+                    device.name,
+                    err
+                ),
+            }
+        }
+
+        let action = if up { "up" } else { "down" };
+        if !json_output {
+            println!("🔌 Bringing {} {} on '{}'...", iface, action, device.name);
+        }
+        let object = format!("network.interface.{}", iface);
+        ubus::call(&client, &device, &session, &object, action, json!({}))
+            .await
+            .context(format!("Failed to bring {} {} on {}", iface, action, device.name))?;
+
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(timeout);
+        let entry = loop {
+            let entry = interface_entry(&client, &device, &session, iface).await?.unwrap_or_default();
+            let settled = entry["up"].as_bool() == Some(up) && !entry["pending"].as_bool().unwrap_or(false);
+            if settled || Instant::now() >= deadline {
+                break entry;
+            }
+            tokio::time::sleep(INTERFACE_POLL_INTERVAL).await;
+        };
+        let reached = entry["up"].as_bool() == Some(up);
+
+        if json_output {
+            let result = InterfaceResult {
+                device_name: device.name.clone(),
+                interface: iface.to_string(),
+                up: entry["up"].as_bool().unwrap_or(false),
+                status: entry.clone(),
+            };
+            println!("{}", serde_json::to_string_pretty(&result)?);
+        } else if reached && up {
+            println!("✅ {} is up after {:.1}s: {}", iface, started.elapsed().as_secs_f64(), format_addresses(&entry));
+        } else if reached {
+            println!("⏸️  {} is down", iface);
+        }
+
+        if !reached {
+            bail!("{} on '{}' did not go {} within {}s", iface, device.name, action, timeout);
+        }
+        Ok(())
+    }
+}
+
+const NETWORK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+// With no failed probe for this long, the restart went unnoticed
+const NETWORK_SETTLE: Duration = Duration::from_secs(15);
+
+// Quote a UCI value the way `uci export` does
+fn uci_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Render `uci get` output for a whole config in `uci export` format, so the
+// snapshot can be restored with `uci import`
+fn uci_export(config: &str, values: &Value) -> String {
+    let mut sections: Vec<(&String, &Value)> = values.as_object().map(|v| v.iter().collect()).unwrap_or_default();
+    sections.sort_by_key(|(_, section)| section[".index"].as_u64().unwrap_or(u64::MAX));
+
+    let mut out = format!("package {}\n", config);
+    for (name, section) in sections {
+        let kind = section[".type"].as_str().unwrap_or_default();
+        out.push('\n');
+        if section[".anonymous"].as_bool().unwrap_or(false) {
+            out.push_str(&format!("config {}\n", kind));
+        } else {
+            out.push_str(&format!("config {} {}\n", kind, uci_quote(name)));
+        }
+        let Some(options) = section.as_object() else {
+            continue;
+        };
+        for (option, value) in options.iter().filter(|(option, _)| !option.starts_with('.')) {
+            match value {
+                Value::Array(items) => {
+                    for item in items.iter().filter_map(Value::as_str) {
+                        out.push_str(&format!("\tlist {} {}\n", option, uci_quote(item)));
+                    }
+                }
+                value => {
+                    let value = value.as_str().map(str::to_string).unwrap_or_else(|| value.to_string());
+                    out.push_str(&format!("\toption {} {}\n", option, uci_quote(&value)));
+                }
+            }
+        }
+    }
+    out
+}
+
+// One ICMP echo with a one second deadline; `None` when there is no usable
+// ping command
+async fn ping(host: &str) -> Option<bool> {
+    let args: &[&str] = if cfg!(windows) {
+        &["-n", "1", "-w", "1000"]
+    } else if cfg!(target_os = "macos") {
+        &["-c", "1", "-W", "1000"]
+    } else {
+        &["-c", "1", "-W", "1"]
+    };
+    let status = tokio::process::Command::new("ping")
+        .args(args)
+        .arg(host)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await
+        .ok()?;
+    Some(status.success())
+}
+
+async fn ubus_reachable(client: &Client, device: &Device) -> bool {
+    match ubus::login(client, device).await {
+        Ok(session) => ubus::call(client, device, &session, "system", "info", json!({})).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+// How the device came back from a network restart
+struct NetworkDowntime {
+    // From the first failed probe to the last probe that still failed
+    downtime: Option<Duration>,
+    // When ubus answered again, since the restart
+    ubus_back: Duration,
+}
+
+// Probe ubus (and ping, when `use_ping`) until both answer again after a
+// failure, or until they kept answering for NETWORK_SETTLE
+async fn wait_for_network(
+    client: &Client,
+    device: &Device,
+    use_ping: bool,
+    requested_at: Instant,
+    timeout: Duration,
+) -> Result<NetworkDowntime> {
+    let host = split_host_port(&device.ip).0.to_string();
+    let mut first_failure: Option<Duration> = None;
+    let mut last_failure: Option<Duration> = None;
+    let mut ubus_back: Option<Duration> = None;
+
+    while requested_at.elapsed() < timeout {
+        let (ubus_ok, ping_ok) = tokio::join!(ubus_reachable(client, device), async {
+            if use_ping {
+                ping(&host).await.unwrap_or(true)
+            } else {
+                true
+            }
+        });
+        let elapsed = requested_at.elapsed();
+        // Only a recovery after the latest ubus failure counts
+        if ubus_ok {
+            ubus_back.get_or_insert(elapsed);
+        } else {
+            ubus_back = None;
+        }
+        if !ubus_ok || !ping_ok {
+            first_failure.get_or_insert(elapsed);
+            last_failure = Some(elapsed);
+        } else if first_failure.is_some() || elapsed >= NETWORK_SETTLE {
+            return Ok(NetworkDowntime {
+                downtime: first_failure.zip(last_failure).map(|(first, last)| last - first + NETWORK_POLL_INTERVAL),
+                ubus_back: ubus_back.unwrap_or(elapsed),
+            });
+        }
+        tokio::time::sleep(NETWORK_POLL_INTERVAL).await;
+    }
+
+    bail!("Device '{}' is not reachable {} seconds after the network restart", device.name, timeout.as_secs());
+}
+
+impl App {
+    pub async fn restart_network(&self, name: Option<&str>, timeout: u64, yes: bool) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+
+        if !yes && !confirm(&format!("Restart the network on '{}'? It may not come back if the config is broken", device.name))? {
+            bail!("Aborted; the network was not restarted");
+        }
+
+        // Keep the config that is about to be applied, in case the device
+        // doesn't come back with it
+        let network = ubus::call(&client, &device, &session, "uci", "get", json!({ "config": "network" }))
+            .await
+            .context(format!("Failed to read the network config of {}", device.name))?;
+        let snapshot = config.new_device_backup_path(self.options.backup_dir.as_deref(), &device.name, "network", "uci")?;
+        let mut file = config::open_private(&snapshot, false)
+            .context(format!("Failed to create {}", snapshot.display()))?;
+        // On disk before the restart, which may take the link down for good
+        std::io::Write::write_all(&mut file, uci_export("network", &network["values"]).as_bytes())
+            .and_then(|_| file.sync_all())
+            .context(format!("Failed to write {}", snapshot.display()))?;
+        println!("💾 Saved the network config of '{}' to {}", device.name, snapshot.display());
+
+        let host = split_host_port(&device.ip).0.to_string();
+        let use_ping = match ping(&host).await {
+            Some(true) => true,
+            Some(false) => {
+                println!("ℹ️  {} does not answer ping; watching ubus only", host);
+                false
+            }
+            None => {
+                println!("ℹ️  No ping command found; watching ubus only");
+                false
+            }
+        };
+
+        let requested_at = Instant::now();
+        self.run_init_script(&client, &device, &session, "network", "restart")
+            .await
+            .context(format!("Failed to restart the network on {}", device.name))?;
+        println!("🔄 Restarting the network on '{}'...", device.name);
+
+        let result = wait_for_network(&client, &device, use_ping, requested_at, Duration::from_secs(timeout))
+            .await
+            .map_err(|err| {
+                err.context(format!(
+                    "The network config it was restarted with is saved in {}",
+                    snapshot.display()
+                ))
+            })?;
+        match result.downtime {
+            Some(downtime) => println!(
+                "✅ '{}' is back: unreachable for about {:.1}s, ubus answering {:.1}s after the restart",
+                device.name,
+                downtime.as_secs_f64(),
+                result.ubus_back.as_secs_f64()
+            ),
+            None => println!("✅ '{}' stayed reachable throughout the restart", device.name),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,327 @@
+use crate::packages::{self, Package, PackageManager};
+use crate::models::Device;
+use crate::ssh;
+use crate::ubus;
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::time::Duration;
+use super::{run_quiet, AggregateResult, App};
+
+#[derive(Serialize)]
+struct PackageList {
+    device_name: String,
+    package_manager: PackageManager,
+    packages: Vec<Package>,
+}
+
+impl App {
+    pub async fn list_packages(
+        &self,
+        name: Option<&str>,
+        upgradable_only: bool,
+        filter: Option<&str>,
+        json_output: bool,
+    ) -> Result<()> {
+        let device = self.config()?.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+
+        let manager = PackageManager::detect(&client, &device, &session).await;
+        let packages = manager
+            .list(&client, &device, &session)
+            .await?
+            .into_iter()
+            .filter(|package| !upgradable_only || package.available_version.is_some())
+            .filter(|package| filter.is_none_or(|filter| package.name.contains(filter)))
+            .collect();
+        let list = PackageList { device_name: device.name, package_manager: manager, packages };
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&list)?);
+            return Ok(());
+        }
+
+        if list.packages.is_empty() {
+            let kind = if upgradable_only { "upgradable" } else { "installed" };
+            let matching = filter.map(|f| format!(" matching '{}'", f)).unwrap_or_default();
+            println!("No {} packages{} on '{}'", kind, matching, list.device_name);
+            return Ok(());
+        }
+
+        let width = list.packages.iter().map(|p| p.name.len()).max().unwrap_or(0).max("PACKAGE".len());
+        let version_width = list
+            .packages
+            .iter()
+            .map(|p| p.installed_version.len())
+            .max()
+            .unwrap_or(0)
+            .max("INSTALLED".len());
+        println!("{:<width$}  {:<version_width$}  AVAILABLE", "PACKAGE", "INSTALLED");
+        for package in &list.packages {
+            println!(
+                "{:<width$}  {:<version_width$}  {}",
+                package.name,
+                package.installed_version,
+                package.available_version.as_deref().unwrap_or("-"),
+            );
+        }
+        let upgradable = list.packages.iter().filter(|p| p.available_version.is_some()).count();
+        println!();
+        println!("{} package(s), {} upgradable", list.packages.len(), upgradable);
+
+        Ok(())
+    }
+}
+
+// Run a package manager command over SSH with its output shown live,
+// turning a non-zero exit status into a `RemoteExit` error
+fn run_package_command(session: &ssh2::Session, command: &str, timeout: Option<Duration>) -> Result<()> {
+    let code = ssh::run_command(session, command, timeout, &mut std::io::stdout())?;
+    if code != 0 {
+        return Err(ssh::RemoteExit { command: command.to_string(), code }.into());
+    }
+    Ok(())
+}
+
+impl App {
+    pub async fn install_packages(
+        &self,
+        name: Option<&str>,
+        packages: &[String],
+        update: bool,
+        update_timeout: u64,
+    ) -> Result<()> {
+        let device = self.config()?.resolve_device(name)?;
+        let connect_timeout = self.options.connect_timeout;
+        let packages = packages.to_vec();
+
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let session = ssh::ssh_connect(&device, connect_timeout)?;
+            let manager = PackageManager::detect_over_ssh(&session)?;
+
+            // Refreshing the index hits the package mirrors, so it gets its
+            // own time limit
+            if update {
+                println!("🔄 Updating package lists on '{}'...", device.name);
+                let timeout = Duration::from_secs(update_timeout);
+                run_package_command(&session, &manager.update_command(), Some(timeout))?;
+            }
+
+            println!("📦 Installing {} on '{}'...", packages.join(", "), device.name);
+            run_package_command(&session, &manager.install_command(&packages)?, None)?;
+            println!("✅ Installed {} on '{}'", packages.join(", "), device.name);
+            Ok(())
+        })
+        .await?
+    }
+
+    pub async fn remove_packages(&self, name: Option<&str>, packages: &[String], force: bool) -> Result<()> {
+        let protected: Vec<&str> = packages
+            .iter()
+            .map(String::as_str)
+            .filter(|package| packages::PROTECTED_PACKAGES.contains(package))
+            .collect();
+        if !protected.is_empty() && !force {
+            bail!(
+                "Refusing to remove {}: wrtcli and remote management need it. Pass --force to remove it anyway.",
+                protected.join(", ")
+            );
+        }
+
+        let device = self.config()?.resolve_device(name)?;
+        let connect_timeout = self.options.connect_timeout;
+        let packages = packages.to_vec();
+
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let session = ssh::ssh_connect(&device, connect_timeout)?;
+            let manager = PackageManager::detect_over_ssh(&session)?;
+
+            println!("🗑️  Removing {} from '{}'...", packages.join(", "), device.name);
+            run_package_command(&session, &manager.remove_command(&packages)?, None)?;
+            println!("✅ Removed {} from '{}'", packages.join(", "), device.name);
+            Ok(())
+        })
+        .await?
+    }
+}
+
+#[derive(Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum UpgradeState {
+    // Found by --check; nothing was changed
+    Pending,
+    Upgraded,
+    Failed,
+    // Kernel-adjacent, left alone without --include-risky
+    Skipped,
+}
+
+#[derive(Serialize)]
+struct PackageUpgrade {
+    name: String,
+    installed_version: String,
+    available_version: String,
+    risky: bool,
+    state: UpgradeState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeviceUpgrades {
+    device_name: String,
+    package_manager: PackageManager,
+    packages: Vec<PackageUpgrade>,
+}
+
+// A package pending on one or more devices
+#[derive(Serialize)]
+struct PendingPackage {
+    name: String,
+    risky: bool,
+    devices: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct UpgradeReport {
+    #[serde(flatten)]
+    devices: AggregateResult<DeviceUpgrades>,
+    packages: Vec<PendingPackage>,
+}
+
+// Refresh the package lists and find the upgradable packages on one device,
+// upgrading them one at a time with `apply`
+fn upgrade_device(
+    device: &Device,
+    connect_timeout: u64,
+    update_timeout: u64,
+    apply: bool,
+    include_risky: bool,
+) -> Result<DeviceUpgrades> {
+    let session = ssh::ssh_connect(device, connect_timeout)?;
+    let manager = PackageManager::detect_over_ssh(&session)?;
+    run_quiet(&session, &manager.update_command(), Some(Duration::from_secs(update_timeout)))?;
+    let upgradable = manager.parse_upgradable(&run_quiet(&session, &manager.list_upgradable_command(), None)?);
+
+    let mut packages = Vec::new();
+    for package in upgradable {
+        let risky = packages::is_risky(&package.name);
+        let (state, error) = if !apply {
+            (UpgradeState::Pending, None)
+        } else if risky && !include_risky {
+            (UpgradeState::Skipped, None)
+        } else {
+            match run_quiet(&session, &manager.upgrade_command(&package.name)?, None) {
+                Ok(_) => (UpgradeState::Upgraded, None),
+                Err(err) => (UpgradeState::Failed, Some(format!("{:#}", err))),
+            }
+        };
+        packages.push(PackageUpgrade {
+            name: package.name,
+            installed_version: package.installed_version,
+            available_version: package.available_version.unwrap_or_default(),
+            risky,
+            state,
+            error,
+        });
+    }
+
+    Ok(DeviceUpgrades { device_name: device.name.clone(), package_manager: manager, packages })
+}
+
+fn upgrade_state_text(upgrade: &PackageUpgrade) -> String {
+    let versions = format!("{} -> {}", upgrade.installed_version, upgrade.available_version);
+    match upgrade.state {
+        UpgradeState::Pending if upgrade.risky => format!("⚠️  {} {} (kernel-adjacent)", upgrade.name, versions),
+        UpgradeState::Pending => format!("⬆️  {} {}", upgrade.name, versions),
+        UpgradeState::Upgraded => format!("✅ {} {}", upgrade.name, versions),
+        UpgradeState::Skipped => format!("⏭️  {} {} (kernel-adjacent; pass --include-risky)", upgrade.name, versions),
+        UpgradeState::Failed => format!(
+            "❌ {} {}: {}",
+            upgrade.name,
+            versions,
+            upgrade.error.as_deref().unwrap_or("failed")
+        ),
+    }
+}
+
+impl App {
+    pub async fn upgrade_packages(
+        &self,
+        apply: bool,
+        include_risky: bool,
+        update_timeout: u64,
+        fail_fast: bool,
+        json_output: bool,
+    ) -> Result<()> {
+        let connect_timeout = self.options.connect_timeout;
+        let results = self
+            .run_on_all_devices(fail_fast, |_, device| async move {
+                tokio::task::spawn_blocking(move || {
+                    upgrade_device(&device, connect_timeout, update_timeout, apply, include_risky)
+                })
+                .await?
+            })
+            .await?;
+
+        let mut pending: std::collections::BTreeMap<&str, PendingPackage> = std::collections::BTreeMap::new();
+        for (name, result) in &results {
+            for upgrade in result.iter().flat_map(|device| &device.packages) {
+                pending
+                    .entry(&upgrade.name)
+                    .or_insert_with(|| PendingPackage {
+                        name: upgrade.name.clone(),
+                        risky: upgrade.risky,
+                        devices: Vec::new(),
+                    })
+                    .devices
+                    .push(name.clone());
+            }
+        }
+        let pending: Vec<PendingPackage> = pending.into_values().collect();
+
+        let total = results.len();
+        let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+        let failed_upgrades = results
+            .iter()
+            .flat_map(|(_, result)| result.iter().flat_map(|device| &device.packages))
+            .filter(|upgrade| upgrade.state == UpgradeState::Failed)
+            .count();
+
+        if json_output {
+            let report = UpgradeReport { devices: AggregateResult::new(results), packages: pending };
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for (name, result) in &results {
+                match result {
+                    Ok(device) if device.packages.is_empty() => println!("✅ {}: up to date", name),
+                    Ok(device) => {
+                        println!("📦 {}: {} upgradable", name, device.packages.len());
+                        for upgrade in &device.packages {
+                            println!("   {}", upgrade_state_text(upgrade));
+                        }
+                    }
+                    Err(err) => println!("❌ {}: {:#}", name, err),
+                }
+            }
+            println!("----------------");
+            if pending.is_empty() {
+                println!("No package updates pending");
+            } else {
+                println!("{} package(s) with updates:", pending.len());
+                for package in &pending {
+                    let marker = if package.risky { " (kernel-adjacent)" } else { "" };
+                    println!("   {}{}: {}", package.name, marker, package.devices.join(", "));
+                }
+            }
+        }
+
+        if failed == total {
+            bail!("Failed to check packages on all {} devices", total);
+        }
+        if failed_upgrades > 0 {
+            bail!("{} package upgrade(s) failed", failed_upgrades);
+        }
+        Ok(())
+    }
+}
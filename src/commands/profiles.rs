@@ -0,0 +1,62 @@
+use crate::config::{self, ConfigManager};
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+use super::App;
+
+#[derive(Serialize)]
+struct ProfileEntry {
+    name: String,
+    config_file: PathBuf,
+    active: bool,
+}
+
+impl App {
+    pub async fn list_profiles(&self, json_output: bool) -> Result<()> {
+        let root = ConfigManager::root()?;
+        let active = root.active_profile()?;
+
+        let mut entries = vec![ProfileEntry {
+            name: config::DEFAULT_PROFILE.to_string(),
+            config_file: root.config_path().to_path_buf(),
+            active: active.is_none(),
+        }];
+        for name in root.list_profiles()? {
+            entries.push(ProfileEntry {
+                config_file: root.profile(&name)?.config_path().to_path_buf(),
+                active: active.as_deref() == Some(name.as_str()),
+                name,
+            });
+        }
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+            return Ok(());
+        }
+
+        for entry in &entries {
+            let marker = if entry.active { "*" } else { " " };
+            println!("{} {} ({})", marker, entry.name, entry.config_file.display());
+        }
+        Ok(())
+    }
+
+    pub async fn create_profile(&self, name: &str) -> Result<()> {
+        let profile = ConfigManager::root()?.create_profile(name)?;
+        println!("✅ Created profile '{}' at {}", name, profile.config_path().display());
+        println!("   Select it with 'wrtcli --profile {}' or 'wrtcli profile use {}'", name, name);
+        Ok(())
+    }
+
+    pub async fn remove_profile(&self, name: &str, force: bool) -> Result<()> {
+        ConfigManager::root()?.remove_profile(name, force)?;
+        println!("🗑️  Removed profile '{}'", name);
+        Ok(())
+    }
+
+    pub async fn use_profile(&self, name: &str) -> Result<()> {
+        ConfigManager::root()?.use_profile(name)?;
+        println!("✅ Now using profile '{}'", name);
+        Ok(())
+    }
+}
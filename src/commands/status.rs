@@ -0,0 +1,421 @@
+use crate::config::ConfigManager;
+use crate::models::{Device, StatusRecord};
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ValueEnum;
+use reqwest::Client;
+use serde_json::json;
+use serde::Serialize;
+use std::time::Duration;
+use super::{AggregateResult, App};
+
+#[derive(Serialize)]
+struct StatusOutput {
+    device_name: String,
+    model: String,
+    hostname: String,
+    uptime: UptimeInfo,
+    load: f64,
+    memory: MemoryInfo,
+}
+
+#[derive(Serialize)]
+struct UptimeInfo {
+    raw_seconds: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formatted: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MemoryInfo {
+    total_kb: u64,
+    free_kb: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_mb: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    free_mb: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    used_percentage: Option<f64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum StatusFormat {
+    Text,
+    Json,
+    Prometheus,
+}
+
+// Status values of a single device, as reported by ubus
+pub struct DeviceStatus {
+    pub model: String,
+    pub hostname: String,
+    pub uptime: u64,
+    pub load: f64,
+    pub total_memory: u64,
+    pub free_memory: u64,
+}
+
+fn status_output(device_name: &str, status: &DeviceStatus, raw: bool) -> StatusOutput {
+    let (total_mb, free_mb, used_percentage) = if !raw {
+        let (t, f, u) = format_memory(status.total_memory, status.free_memory);
+        (Some(t), Some(f), Some(u))
+    } else {
+        (None, None, None)
+    };
+
+    StatusOutput {
+        device_name: device_name.to_string(),
+        model: status.model.clone(),
+        hostname: status.hostname.clone(),
+        uptime: UptimeInfo {
+            raw_seconds: status.uptime,
+            formatted: if !raw { Some(format_uptime(status.uptime)) } else { None },
+        },
+        load: status.load,
+        memory: MemoryInfo {
+            total_kb: status.total_memory,
+            free_kb: status.free_memory,
+            total_mb,
+            free_mb,
+            used_percentage,
+        },
+    }
+}
+
+fn print_status_text(device_name: &str, status: &DeviceStatus, raw: bool) {
+    println!("Device Status: {}", device_name);
+    println!("----------------");
+    println!("📍 Model: {}", status.model);
+    println!("🏷️  Hostname: {}", status.hostname);
+
+    if raw {
+        println!("⏰ Uptime: {} seconds", status.uptime);
+        println!("🔄 Load: {:.2}", status.load);
+        println!("💾 Memory:");
+        println!("   Total: {} KB", status.total_memory);
+        println!("   Free: {} KB", status.free_memory);
+    } else {
+        println!("⏰ Uptime: {}", format_uptime(status.uptime));
+        println!("🔄 Load: {:.2}", status.load);
+
+        let (total_mb, free_mb, used_percentage) = format_memory(status.total_memory, status.free_memory);
+        println!("💾 Memory:");
+        println!("   Total: {:.1} MB", total_mb);
+        println!("   Free: {:.1} MB", free_mb);
+        println!("   Used: {:.1}%", used_percentage);
+    }
+}
+
+// Render device statuses in the Prometheus text exposition format, in base
+// units (seconds, bytes) regardless of `--raw`. procd reports memory in
+// bytes already, and the load as the kernel's fixed-point value (x65536).
+fn render_prometheus(statuses: &[(&str, &DeviceStatus)]) -> String {
+    type Metric = (&'static str, &'static str, fn(&DeviceStatus) -> String);
+    let metrics: [Metric; 5] = [
+        ("wrtcli_uptime_seconds", "Time since the device booted", |s| s.uptime.to_string()),
+        ("wrtcli_load1", "One minute load average", |s| (s.load / LOAD_SCALE).to_string()),
+        ("wrtcli_memory_total_bytes", "Total memory", |s| s.total_memory.to_string()),
+        ("wrtcli_memory_free_bytes", "Free memory", |s| s.free_memory.to_string()),
+        ("wrtcli_memory_used_bytes", "Used memory", |s| s.total_memory.saturating_sub(s.free_memory).to_string()),
+    ];
+
+    let mut output = String::new();
+    for (metric, help, value) in metrics {
+        output.push_str(&format!("# HELP {} {}\n", metric, help));
+        output.push_str(&format!("# TYPE {} gauge\n", metric));
+        for (device_name, status) in statuses {
+            output.push_str(&format!(
+                "{}{{device=\"{}\"}} {}\n",
+                metric,
+                escape_label_value(device_name),
+                value(status)
+            ));
+        }
+    }
+    output
+}
+
+// `system info` load averages are fixed-point with 16 fractional bits
+const LOAD_SCALE: f64 = 65536.0;
+
+// Escape a label value as required by the Prometheus exposition format
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Helper function to format uptime into a human readable format
+pub fn format_uptime(seconds: u64) -> String {
+    let days = seconds / (24 * 3600);
+    let hours = (seconds % (24 * 3600)) / 3600;
+    let minutes = (seconds % 3600) / 60;
+    let secs = seconds % 60;
+
+    let mut parts = Vec::new();
+    if days > 0 {
+        parts.push(format!("{} days", days));
+    }
+    if hours > 0 {
+        parts.push(format!("{} hours", hours));
+    }
+    if minutes > 0 {
+        parts.push(format!("{} minutes", minutes));
+    }
+    if secs > 0 || parts.is_empty() {
+        parts.push(format!("{} seconds", secs));
+    }
+
+    parts.join(", ")
+}
+
+// Helper function to format memory into human readable format
+fn format_memory(total_kb: u64, free_kb: u64) -> (f64, f64, f64) {
+    let total_mb = total_kb as f64 / 1024.0;
+    let free_mb = free_kb as f64 / 1024.0;
+    let used_percentage = ((total_kb - free_kb) as f64 / total_kb as f64) * 100.0;
+    
+    (total_mb, free_mb, used_percentage)
+}
+
+pub async fn fetch_status(client: &Client, device: &Device) -> Result<DeviceStatus> {
+    let session = ubus::login(client, device).await?;
+
+    // Get system info
+    let board_info = ubus::call(client, device, &session, "system", "board", json!({})).await?;
+
+    // Get system status
+    let system_info = ubus::call(client, device, &session, "system", "info", json!({})).await?;
+
+    Ok(DeviceStatus {
+        model: board_info["model"].as_str().unwrap_or("Unknown").to_string(),
+        hostname: board_info["hostname"].as_str().unwrap_or("Unknown").to_string(),
+        uptime: system_info["uptime"].as_u64().unwrap_or(0),
+        load: system_info["load"][0].as_f64().unwrap_or(0.0),
+        total_memory: system_info["memory"]["total"].as_u64().unwrap_or(0),
+        free_memory: system_info["memory"]["free"].as_u64().unwrap_or(0),
+    })
+}
+
+fn record_status(config: &ConfigManager, device_name: &str, status: &DeviceStatus) -> Result<()> {
+    let record = StatusRecord {
+        timestamp: Utc::now(),
+        uptime: status.uptime,
+        load: status.load,
+        memory_total_kb: status.total_memory,
+        memory_free_kb: status.free_memory,
+    };
+    config.append_history(device_name, &record)
+}
+
+impl App {
+    pub async fn get_status(
+        &self,
+        name: Option<&str>,
+        raw: bool,
+        format: StatusFormat,
+        record: bool,
+    ) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let status = fetch_status(&self.client(&device)?, &device).await?;
+
+        if record {
+            record_status(config, &device.name, &status)?;
+        }
+
+        match format {
+            StatusFormat::Json => {
+                let output = status_output(&device.name, &status, raw);
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            }
+            StatusFormat::Prometheus => print!("{}", render_prometheus(&[(&device.name, &status)])),
+            StatusFormat::Text => print_status_text(&device.name, &status, raw),
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_status_all(
+        &self,
+        raw: bool,
+        format: StatusFormat,
+        fail_fast: bool,
+        record: bool,
+    ) -> Result<()> {
+        let results = self
+            .run_on_all_devices(fail_fast, |client, device| async move { fetch_status(&client, &device).await })
+            .await?;
+
+        if record {
+            let config = self.config()?;
+            for (name, result) in &results {
+                if let Ok(status) = result {
+                    record_status(config, name, status)?;
+                }
+            }
+        }
+        let total = results.len();
+        let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+
+        match format {
+            StatusFormat::Json => {
+                let results = results
+                    .into_iter()
+                    .map(|(name, result)| {
+                        let output = result.map(|status| status_output(&name, &status, raw));
+                        (name, output)
+                    })
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&AggregateResult::new(results))?);
+            }
+            StatusFormat::Prometheus => {
+                let statuses: Vec<(&str, &DeviceStatus)> = results
+                    .iter()
+                    .filter_map(|(name, result)| result.as_ref().ok().map(|s| (name.as_str(), s)))
+                    .collect();
+                print!("{}", render_prometheus(&statuses));
+                for (name, result) in &results {
+                    if let Err(err) = result {
+                        eprintln!("❌ {}: {:#}", name, err);
+                    }
+                }
+            }
+            StatusFormat::Text => {
+                for (index, (name, result)) in results.iter().enumerate() {
+                    if index > 0 {
+                        println!();
+                    }
+                    match result {
+                        Ok(status) => print_status_text(name, status, raw),
+                        Err(err) => println!("❌ {}: {:#}", name, err),
+                    }
+                }
+            }
+        }
+
+        // Partial failures are reported per device; only fail when nothing worked
+        if failed == total {
+            bail!("Failed to get status from all {} devices", total);
+        }
+
+        Ok(())
+    }
+}
+
+// Parse a duration such as "90s", "30m", "12h" or "7d"
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number
+        .parse()
+        .context(format!("Invalid duration '{}'", value))?;
+
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 3600,
+        "d" => number * 24 * 3600,
+        _ => bail!("Invalid duration '{}': use a number followed by s, m, h or d", value),
+    };
+
+    Ok(Duration::from_secs(seconds))
+}
+
+// Parse a point in time given as a date (2024-01-01), an RFC 3339
+// timestamp, or a duration before now (24h, 7d)
+fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+    }
+
+    let duration = parse_duration(value)
+        .context(format!("Invalid time '{}': use a date, an RFC 3339 timestamp or a duration like 24h", value))?;
+    Ok(Utc::now() - chrono::Duration::from_std(duration)?)
+}
+
+impl App {
+    pub async fn show_history(&self, name: Option<&str>, since: Option<&str>) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let since = since.map(parse_since).transpose()?;
+
+        let records: Vec<StatusRecord> = config
+            .load_history(&device.name)?
+            .into_iter()
+            .filter(|record| since.is_none_or(|since| record.timestamp >= since))
+            .collect();
+
+        let (Some(first), Some(last)) = (records.first(), records.last()) else {
+            println!(
+                "No status history recorded for '{}'. Use 'wrtcli status {} --record' to record samples.",
+                device.name, device.name
+            );
+            return Ok(());
+        };
+
+        let summarize = |values: Vec<f64>| {
+            let min = values.iter().cloned().fold(f64::INFINITY, f64::min);
+            let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+            let avg = values.iter().sum::<f64>() / values.len() as f64;
+            (min, avg, max)
+        };
+        let (load_min, load_avg, load_max) = summarize(records.iter().map(|r| r.load).collect());
+        let (mem_min, mem_avg, mem_max) = summarize(
+            records
+                .iter()
+                .map(|r| r.memory_total_kb.saturating_sub(r.memory_free_kb) as f64 / 1024.0)
+                .collect(),
+        );
+
+        println!("Status History: {}", device.name);
+        println!("----------------");
+        println!("📈 Samples: {}", records.len());
+        println!("🕒 From: {}", first.timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
+        println!("🕒 To: {}", last.timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
+        println!("🔄 Load: min {:.2} / avg {:.2} / max {:.2}", load_min, load_avg, load_max);
+        println!("💾 Memory used: min {:.1} MB / avg {:.1} MB / max {:.1} MB", mem_min, mem_avg, mem_max);
+        println!("⏰ Last uptime: {}", format_uptime(last.uptime));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prometheus_metrics_are_in_base_units() {
+        // As reported by `ubus call system info` on a 128 MB router
+        let status = DeviceStatus {
+            model: "TP-Link Archer C7 v5".to_string(),
+            hostname: "OpenWrt".to_string(),
+            uptime: 86400,
+            load: 14176.0,
+            total_memory: 129048576,
+            free_memory: 80588800,
+        };
+        let output = render_prometheus(&[("gw \"office\"", &status)]);
+        let lines: Vec<&str> = output.lines().filter(|line| !line.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            [
+                "wrtcli_uptime_seconds{device=\"gw \\\"office\\\"\"} 86400",
+                "wrtcli_load1{device=\"gw \\\"office\\\"\"} 0.21630859375",
+                "wrtcli_memory_total_bytes{device=\"gw \\\"office\\\"\"} 129048576",
+                "wrtcli_memory_free_bytes{device=\"gw \\\"office\\\"\"} 80588800",
+                "wrtcli_memory_used_bytes{device=\"gw \\\"office\\\"\"} 48459776",
+            ]
+        );
+        assert!(output.contains("# TYPE wrtcli_load1 gauge\n"));
+    }
+}
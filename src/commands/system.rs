@@ -0,0 +1,167 @@
+use crate::models::Device;
+use crate::ssh;
+use crate::system;
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde_json::json;
+use std::time::{Duration, Instant};
+use super::status::{fetch_status, format_uptime};
+use super::App;
+
+const REBOOT_POLL_INTERVAL_SECS: u64 = 2;
+
+// Poll the device after a reboot has been requested until a fresh login
+// succeeds and its uptime shows it actually restarted. Returns the time from
+// the reboot request until the device was reachable again.
+pub async fn wait_for_reboot(
+    client: &Client,
+    device: &Device,
+    uptime_before: u64,
+    requested_at: Instant,
+    timeout: Duration,
+) -> Result<Duration> {
+    while requested_at.elapsed() < timeout {
+        tokio::time::sleep(Duration::from_secs(REBOOT_POLL_INTERVAL_SECS)).await;
+
+        ubus::invalidate_session(device);
+        match fetch_status(client, device).await {
+            // A device that never went down would report the old uptime plus
+            // the time elapsed since the request
+            Ok(status) if status.uptime < uptime_before + requested_at.elapsed().as_secs() => {
+                return Ok(requested_at.elapsed());
+            }
+            // Still shutting down, or already down and not answering yet
+            Ok(_) | Err(_) => {}
+        }
+    }
+
+    bail!(
+        "Device '{}' did not come back within {} seconds. Check its power and network link, \
+         then run 'wrtcli status {}' to see whether it is reachable.",
+        device.name,
+        timeout.as_secs(),
+        device.name
+    );
+}
+
+impl App {
+    pub async fn reboot_device(
+        &self,
+        name: Option<&str>,
+        wait: bool,
+        timeout: u64,
+    ) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+
+        let client = self.client(&device)?;
+        let uptime_before = if wait {
+            fetch_status(&client, &device).await?.uptime
+        } else {
+            0
+        };
+
+        let session = ubus::login(&client, &device).await?;
+
+        // Send reboot command
+        ubus::call(&client, &device, &session, "system", "reboot", json!({})).await?;
+        let requested_at = Instant::now();
+
+        println!("🔄 Rebooting device '{}'...", device.name);
+
+        if wait {
+            println!("⏳ Waiting up to {} seconds for the device to come back...", timeout);
+            let downtime = wait_for_reboot(
+                &client,
+                &device,
+                uptime_before,
+                requested_at,
+                Duration::from_secs(timeout),
+            )
+            .await?;
+            println!(
+                "✅ Device '{}' is back online (down for {})",
+                device.name,
+                format_uptime(downtime.as_secs())
+            );
+        }
+
+        Ok(())
+    }
+}
+
+// How long the system service gets to apply a new hostname
+const HOSTNAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl App {
+    pub async fn set_system(&self, name: Option<&str>, hostname: Option<&str>, timezone: Option<&str>) -> Result<()> {
+        if let Some(hostname) = hostname {
+            system::validate_hostname(hostname)?;
+        }
+        let rule = timezone.map(system::zone_rule).transpose()?;
+
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+
+        let (section, current) = system::section(&client, &device, &session).await?;
+        let mut values = json!({});
+        let mut changes = Vec::new();
+        if let Some(hostname) = hostname {
+            values["hostname"] = json!(hostname);
+            changes.push(format!("hostname {} → {}", current["hostname"].as_str().unwrap_or("(unset)"), hostname));
+        }
+        if let (Some(zonename), Some(rule)) = (timezone, &rule) {
+            values["zonename"] = json!(zonename);
+            values["timezone"] = json!(rule);
+            changes.push(format!("time zone {} → {}", current["zonename"].as_str().unwrap_or("(unset)"), zonename));
+        }
+        system::set(&client, &device, &session, &section, values).await?;
+        println!("💾 Saved on '{}': {}", device.name, changes.join(", "));
+
+        self.run_init_script(&client, &device, &session, "system", "reload")
+            .await
+            .context(format!(
+                "Failed to reload the system service on {}; the new settings are saved and apply at the next boot",
+                device.name
+            ))?;
+        if let (Some(zonename), Some(rule)) = (timezone, &rule) {
+            system::verify_zone(&client, &device, &session, zonename, rule).await?;
+            println!("✅ '{}' now has the time zone {}", device.name, zonename);
+        }
+        if let Some(hostname) = hostname {
+            system::wait_for_hostname(&client, &device, &session, hostname, HOSTNAME_TIMEOUT).await?;
+            println!("✅ '{}' now reports the hostname '{}'", device.name, hostname);
+            if device.name != hostname {
+                println!(
+                    "ℹ️  wrtcli still calls it '{}'; the device name in the config is not changed",
+                    device.name
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+impl App {
+    // Run `/etc/init.d/<service> <action>`. rpcd's `rc` object starts it in
+    // the background; older releases without it get it over SSH.
+    pub async fn run_init_script(&self, client: &Client, device: &Device, session: &str, service: &str, action: &str) -> Result<()> {
+        let rc = ubus::call(client, device, session, "rc", "init", json!({ "name": service, "action": action })).await;
+        if let Err(err) = rc {
+            tracing::debug!("rc init on {} failed, running {} {} over SSH: {:#}", device.name, service, action, err);
+            let ssh_device = device.clone();
+            let connect_timeout = self.options.connect_timeout;
+            let command = format!("/etc/init.d/{} {} >/dev/null 2>&1 &", service, action);
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                let session = ssh::ssh_connect(&ssh_device, connect_timeout)?;
+                ssh::run_command(&session, &command, Some(Duration::from_secs(10)), &mut std::io::sink())?;
+                Ok(())
+            })
+            .await??;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use serde::Serialize;
+use super::status::format_uptime;
+use super::App;
+
+impl App {
+    pub async fn ubus_list(&self, name: Option<&str>, pattern: &str) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+
+        let objects = ubus::list(&client, &device, pattern).await?;
+        let Some(objects) = objects.as_object().filter(|o| !o.is_empty()) else {
+            println!("No ubus objects matching '{}' on '{}'", pattern, device.name);
+            return Ok(());
+        };
+
+        let mut names: Vec<&String> = objects.keys().collect();
+        names.sort();
+        for object in names {
+            println!("📦 {}", object);
+            if let Some(methods) = objects[object].as_object() {
+                let mut methods: Vec<(&String, &serde_json::Value)> = methods.iter().collect();
+                methods.sort_by(|a, b| a.0.cmp(b.0));
+                for (method, args) in methods {
+                    let args = args
+                        .as_object()
+                        .map(|args| {
+                            args.iter()
+                                .map(|(arg, kind)| format!("{}: {}", arg, ubus_type_name(kind)))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        })
+                        .unwrap_or_default();
+                    println!("   {}({})", method, args);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Map the blobmsg type codes reported by `ubus list` to readable names
+fn ubus_type_name(kind: &serde_json::Value) -> String {
+    match kind.as_str() {
+        Some(name) => name.to_lowercase(),
+        None => match kind.as_u64() {
+            Some(1) => "array".to_string(),
+            Some(2) => "table".to_string(),
+            Some(3) => "string".to_string(),
+            Some(4) => "int64".to_string(),
+            Some(5) => "int32".to_string(),
+            Some(6) => "int16".to_string(),
+            Some(7) => "boolean".to_string(),
+            Some(8) => "double".to_string(),
+            _ => kind.to_string(),
+        },
+    }
+}
+
+impl App {
+    pub async fn ubus_call(
+        &self,
+        name: Option<&str>,
+        object: &str,
+        method: &str,
+        params: Option<&str>,
+    ) -> Result<()> {
+        let params: serde_json::Value = match params {
+            Some(params) => serde_json::from_str(params)
+                .context(format!("Invalid JSON params: {}", params))?,
+            None => json!({}),
+        };
+        if !params.is_object() {
+            bail!("ubus params must be a JSON object, e.g. '{{\"name\": \"eth0\"}}'");
+        }
+
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+
+        let result = ubus::call(&client, &device, &session, object, method, params).await?;
+        println!("{}", serde_json::to_string_pretty(&result)?);
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct WhoamiOutput {
+    device_name: String,
+    user: String,
+    timeout_seconds: u64,
+    acls: serde_json::Value,
+}
+
+impl App {
+    pub async fn whoami(&self, name: Option<&str>, json_output: bool) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+
+        let session = ubus::login_details(&client, &device).await?;
+        let output = WhoamiOutput {
+            device_name: device.name.clone(),
+            user: session["data"]["username"]
+                .as_str()
+                .unwrap_or(&device.user)
+                .to_string(),
+            timeout_seconds: session["timeout"].as_u64().unwrap_or(0),
+            acls: session["acls"].clone(),
+        };
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            return Ok(());
+        }
+
+        println!("Session: {}", output.device_name);
+        println!("----------------");
+        println!("👤 User: {}", output.user);
+        println!("⏲️  Session timeout: {}", format_uptime(output.timeout_seconds));
+        println!("🔐 Access:");
+
+        let Some(scopes) = output.acls.as_object().filter(|s| !s.is_empty()) else {
+            println!("   (no ACLs granted)");
+            return Ok(());
+        };
+
+        let mut scope_names: Vec<&String> = scopes.keys().collect();
+        scope_names.sort();
+        for scope in scope_names {
+            println!("   {}:", scope);
+            match &scopes[scope] {
+                serde_json::Value::Object(objects) => {
+                    let mut names: Vec<&String> = objects.keys().collect();
+                    names.sort();
+                    for object in names {
+                        let permissions = objects[object]
+                            .as_array()
+                            .map(|p| p.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>().join(", "))
+                            .unwrap_or_default();
+                        println!("     {}: {}", object, permissions);
+                    }
+                }
+                serde_json::Value::Array(entries) => {
+                    let entries: Vec<&str> = entries.iter().filter_map(|e| e.as_str()).collect();
+                    println!("     {}", entries.join(", "));
+                }
+                other => println!("     {}", other),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+use crate::config;
+use crate::logging;
+use crate::models::Device;
+use crate::ubus;
+use crate::wireless::{self, RadioStatus};
+use anyhow::{bail, Result};
+use reqwest::Client;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use super::status::parse_duration;
+use super::{AggregateResult, App};
+
+// How long netifd gets to bring radios up or down after a reload
+const RADIO_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn radio_state_text(radio: &str, status: Option<&RadioStatus>) -> String {
+    match status {
+        None => format!("❓ {}: not reported by netifd", radio),
+        Some(status) if status.pending => format!("⏳ {}: still pending", radio),
+        Some(status) if status.up && status.interfaces.is_empty() => format!("✅ {}: up", radio),
+        Some(status) if status.up => format!("✅ {}: up ({})", radio, status.interfaces.join(", ")),
+        Some(_) => format!("📴 {}: off", radio),
+    }
+}
+
+impl App {
+    pub async fn set_wifi(&self, name: Option<&str>, radio: Option<&str>, enable: bool, timer: Option<&str>) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let delay = timer.map(parse_duration).transpose()?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+        let radios = wireless::select_radios(&device, wireless::radios(&client, &device, &session).await?, radio)?;
+
+        println!(
+            "📶 {} {} on '{}'...",
+            if enable { "Enabling" } else { "Disabling" },
+            radios.join(", "),
+            device.name
+        );
+        wireless::set_disabled(&client, &device, &session, &radios, !enable).await?;
+        // Schedule the timer before reloading, so the radios come back even
+        // if this run loses its connection
+        if let Some(delay) = delay {
+            let at = wireless::schedule_enable(&client, &device, &session, &radios, delay).await?;
+            println!("⏰ The radios will be turned back on at {} (device time)", at);
+        } else if enable && wireless::cancel_timer(&client, &device, &session).await.unwrap_or(false) {
+            println!("⏰ Cancelled the pending re-enable timer");
+        }
+        wireless::reload(&client, &device, &session).await?;
+
+        let (status, settled) =
+            wireless::wait_for_radios(&client, &device, &session, &radios, enable, RADIO_TIMEOUT).await?;
+        for radio in &radios {
+            println!("{}", radio_state_text(radio, status.get(radio)));
+        }
+        if !settled {
+            bail!(
+                "Radios on '{}' did not turn {} within {}s",
+                device.name,
+                if enable { "on" } else { "off" },
+                RADIO_TIMEOUT.as_secs()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl App {
+    pub async fn restart_wifi(&self, name: Option<&str>, radio: Option<&str>) -> Result<()> {
+        let config = self.config()?;
+        let device = config.resolve_device(name)?;
+        let client = self.client(&device)?;
+        let session = ubus::login(&client, &device).await?;
+        let radios = wireless::select_radios(&device, wireless::radios(&client, &device, &session).await?, radio)?;
+
+        // Disabled radios stay down, so only wait for the others
+        let status = wireless::status(&client, &device, &session).await?;
+        let (disabled, radios): (Vec<String>, Vec<String>) =
+            radios.into_iter().partition(|radio| status.get(radio).is_some_and(|s| s.disabled));
+        if radios.is_empty() {
+            let which = match disabled.as_slice() {
+                [radio] => format!("{} on '{}' is", radio, device.name),
+                _ => format!("All radios on '{}' are", device.name),
+            };
+            bail!("{} disabled; turn it on with 'wrtcli wifi enable'", which);
+        }
+        for radio in &disabled {
+            println!("📴 {}: disabled, skipped", radio);
+        }
+
+        if wireless::connected_over_wifi(&client, &device, &session).await == Some(true) {
+            println!(
+                "⚠️  This machine is connected to '{}' over its wifi; the connection will drop while the radios restart",
+                device.name
+            );
+        }
+
+        println!("🔄 Restarting {} on '{}'...", radios.join(", "), device.name);
+        let started = Instant::now();
+        wireless::down(&client, &device, &session, radio).await?;
+        wireless::wait_for_radios(&client, &device, &session, &radios, false, RADIO_TIMEOUT).await?;
+        wireless::up(&client, &device, &session, radio).await?;
+        let (status, settled) =
+            wireless::wait_for_radios(&client, &device, &session, &radios, true, RADIO_TIMEOUT).await?;
+
+        for radio in &radios {
+            println!("{}", radio_state_text(radio, status.get(radio)));
+        }
+        if !settled {
+            bail!("Radios on '{}' did not come back up within {}s", device.name, RADIO_TIMEOUT.as_secs());
+        }
+        println!("⏱️  Wireless back up after {:.1}s", started.elapsed().as_secs_f64());
+        Ok(())
+    }
+}
+
+// What `wifi set` changes: the interfaces named `target` (a UCI section name
+// or an SSID) get `new_ssid` and/or `new_password`. `Some(None)` for the
+// password means it is read with a prompt.
+pub struct WifiUpdate {
+    pub target: String,
+    pub new_ssid: Option<String>,
+    pub new_password: Option<Option<String>>,
+}
+
+// The interfaces `wifi set` updated on one device, as "section (radio)".
+// With --all, devices that don't have the network are skipped instead.
+#[derive(Serialize)]
+struct WifiChange {
+    interfaces: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped: Option<String>,
+}
+
+async fn update_wifi_ifaces(
+    client: &Client,
+    device: &Device,
+    target: &str,
+    ssid: Option<&str>,
+    key: Option<&str>,
+    skip_missing: bool,
+) -> Result<WifiChange> {
+    let session = ubus::login(client, device).await?;
+    let ifaces = wireless::ifaces(client, device, &session).await?;
+    if skip_missing && !ifaces.iter().any(|iface| iface.section == target || iface.ssid == target) {
+        return Ok(WifiChange { interfaces: Vec::new(), skipped: Some("network not present".to_string()) });
+    }
+    let ifaces = wireless::find_ifaces(device, ifaces, target)?;
+    if key.is_some() {
+        for iface in &ifaces {
+            wireless::ensure_encrypted(device, iface)?;
+        }
+    }
+
+    for iface in &ifaces {
+        if let Err(err) = wireless::set_iface(client, device, &session, iface, ssid, key).await {
+            let _ = wireless::revert(client, device, &session).await;
+            return Err(err);
+        }
+    }
+    wireless::commit(client, device, &session).await?;
+    wireless::reload(client, device, &session).await?;
+
+    if !wireless::wait_for_ifaces(client, device, &session, &ifaces, ssid, key, RADIO_TIMEOUT).await? {
+        bail!(
+            "The new settings were saved on '{}', but network.wireless status did not show them within {}s",
+            device.name,
+            RADIO_TIMEOUT.as_secs()
+        );
+    }
+    Ok(WifiChange {
+        interfaces: ifaces
+            .iter()
+            .map(|iface| format!("{} ({})", iface.section, iface.radio))
+            .collect(),
+        skipped: None,
+    })
+}
+
+impl App {
+    pub async fn set_wifi_network(
+        &self,
+        name: Option<&str>,
+        all: bool,
+        fail_fast: bool,
+        json_output: bool,
+        update: WifiUpdate,
+    ) -> Result<()> {
+        if let Some(ssid) = &update.new_ssid {
+            wireless::validate_ssid(ssid)?;
+        }
+        let key = match update.new_password {
+            Some(Some(key)) => Some(key),
+            Some(None) => Some(config::read_secret("New wifi passphrase", true)?),
+            None => None,
+        };
+        if let Some(key) = &key {
+            wireless::validate_passphrase(key)?;
+            logging::register_secret(key);
+        }
+        let change = match (&update.new_ssid, &key) {
+            (Some(ssid), Some(_)) => format!("SSID '{}' and the new passphrase", ssid),
+            (Some(ssid), None) => format!("SSID '{}'", ssid),
+            _ => "the new passphrase".to_string(),
+        };
+
+        if !all {
+            let device = self.config()?.resolve_device(name)?;
+            let client = self.client(&device)?;
+            let result =
+                update_wifi_ifaces(&client, &device, &update.target, update.new_ssid.as_deref(), key.as_deref(), false).await?;
+            if json_output {
+                println!("{}", serde_json::to_string_pretty(&result)?);
+            } else {
+                println!("✅ Updated {} on '{}' to {}", result.interfaces.join(", "), device.name, change);
+            }
+            return Ok(());
+        }
+
+        let results = self
+            .run_on_all_devices(fail_fast, |client, device| {
+                let target = update.target.clone();
+                let ssid = update.new_ssid.clone();
+                let key = key.clone();
+                async move { update_wifi_ifaces(&client, &device, &target, ssid.as_deref(), key.as_deref(), true).await }
+            })
+            .await?;
+        let total = results.len();
+        let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+        let skipped = results
+            .iter()
+            .filter(|(_, result)| matches!(result, Ok(result) if result.skipped.is_some()))
+            .count();
+        let updated = total - failed - skipped;
+
+        if json_output {
+            println!("{}", serde_json::to_string_pretty(&AggregateResult::new(results))?);
+        } else {
+            let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("DEVICE".len());
+            println!("{:<width$}  RESULT", "DEVICE");
+            for (name, result) in &results {
+                match result {
+                    Ok(WifiChange { skipped: Some(reason), .. }) => println!("{:<width$}  ⏭️  {}", name, reason),
+                    Ok(result) => println!("{:<width$}  ✅ {}", name, result.interfaces.join(", ")),
+                    Err(err) => println!("{:<width$}  ❌ {:#}", name, err),
+                }
+            }
+            println!();
+            println!("{} of {} device(s) updated to {}, {} skipped", updated, total, change, skipped);
+        }
+
+        // Partial failures are reported per device; only fail when no device
+        // was updated
+        if updated == 0 {
+            if failed == 0 {
+                bail!("No device has a wifi network '{}'", update.target);
+            }
+            bail!("Failed to update {} of {} devices ({} skipped)", failed, total, skipped);
+        }
+        Ok(())
+    }
+}
@@ -1,77 +1,944 @@
-use crate::models::{Config, Device};
-use anyhow::{Context, Result};
-use dirs;
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
+use crate::encryption;
+use crate::netrc;
+use crate::models::{Config, ConfigFormat, ConflictPolicy, Device, Secret, StatusRecord};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const MAX_HISTORY_BYTES: u64 = 1024 * 1024;
+const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+// Config file chosen with `--config`/WRTCLI_CONFIG, used by every
+// ConfigManager created afterwards in this process
+static CONFIG_OVERRIDE: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+pub fn set_config_override(path: Option<PathBuf>) {
+    *CONFIG_OVERRIDE.lock().unwrap() = path;
+}
+
+pub fn config_override() -> Option<PathBuf> {
+    CONFIG_OVERRIDE.lock().unwrap().clone()
+}
+
+// Profile chosen with `--profile`/WRTCLI_PROFILE, taking precedence over
+// the one recorded by `profile use`
+static PROFILE_OVERRIDE: Mutex<Option<String>> = Mutex::new(None);
+
+pub fn set_profile_override(profile: Option<String>) {
+    *PROFILE_OVERRIDE.lock().unwrap() = profile;
+}
+
+const APP_DIR: &str = "wrtcli";
+const CONFIG_FILE: &str = "config.toml";
+const JSON_CONFIG_FILE: &str = "config.json";
+const PROFILES_DIR: &str = "profiles";
+// Top-level state kept across runs, currently just the profile in use
+const STATE_FILE: &str = "state.toml";
+// Name that selects the top-level config instead of a profile
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Default, Serialize, serde::Deserialize)]
+struct State {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+}
+
+// The config file in `dir`: config.toml, or config.json when only that one
+// exists
+fn config_file_in(dir: &Path) -> PathBuf {
+    let json = dir.join(JSON_CONFIG_FILE);
+    if json.exists() && !dir.join(CONFIG_FILE).exists() {
+        return json;
+    }
+    dir.join(CONFIG_FILE)
+}
+
+// System-wide config, used read-only when there is no home directory (e.g.
+// in a minimal container or for a systemd DynamicUser)
+const SYSTEM_CONFIG: &str = "/etc/wrtcli/config.toml";
+static NO_HOME_WARNING: std::sync::Once = std::sync::Once::new();
+
+// The home directory and the platform's config, data and cache
+// directories the config is looked up in. Taken from the environment,
+// except in tests, which pass their own.
+#[derive(Clone, Debug, Default)]
+struct BaseDirs {
+    home: Option<PathBuf>,
+    config: Option<PathBuf>,
+    data: Option<PathBuf>,
+    cache: Option<PathBuf>,
+}
+
+impl BaseDirs {
+    fn from_env() -> Self {
+        let home = dirs::home_dir();
+        if cfg!(target_os = "linux") {
+            return Self::xdg(home, |name| std::env::var_os(name));
+        }
+        Self { config: dirs::config_dir(), data: dirs::data_dir(), cache: dirs::cache_dir(), home }
+    }
+
+    // The XDG base directory rules used on Linux: XDG_CONFIG_HOME and friends
+    // when set to an absolute path, otherwise their defaults under the home
+    // directory
+    fn xdg(home: Option<PathBuf>, var: impl Fn(&str) -> Option<std::ffi::OsString>) -> Self {
+        let dir = |name: &str, default: &str| {
+            var(name)
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute())
+                .or_else(|| home.as_ref().map(|home| home.join(default)))
+        };
+        Self {
+            config: dir("XDG_CONFIG_HOME", ".config"),
+            data: dir("XDG_DATA_HOME", ".local/share"),
+            cache: dir("XDG_CACHE_HOME", ".cache"),
+            home,
+        }
+    }
+
+    // The pre-XDG layout, with everything under ~/.wrtcli
+    fn legacy_dir(&self) -> Option<PathBuf> {
+        self.home.as_ref().map(|home| home.join(".wrtcli"))
+    }
+}
+
+const PRIVATE_FILE_MODE: u32 = 0o600;
+const PRIVATE_DIR_MODE: u32 = 0o700;
+
+// Restrict `path` to the current user. Permission bits are a Unix concept,
+// so this does nothing elsewhere.
+#[cfg(unix)]
+pub fn set_private_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .context(format!("Failed to set permissions on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn set_private_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_shared(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o077 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_shared(_path: &Path) -> bool {
+    false
+}
+
+// Create a directory, making it private to the user if it didn't exist yet.
+// Existing directories are left alone since they may be shared on purpose.
+pub fn create_private_dir(path: &Path) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(path).context(format!("Failed to create {}", path.display()))?;
+    set_private_mode(path, PRIVATE_DIR_MODE)
+}
+
+// Open a file for writing that is created readable by the user only
+pub fn open_private(path: &Path, append: bool) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true);
+    if append {
+        options.append(true);
+    } else {
+        options.truncate(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(PRIVATE_FILE_MODE);
+    }
+    options.open(path)
+}
+
+fn is_app_dir(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name == APP_DIR || name == ".wrtcli")
+}
+
+// Exclusive advisory lock on the config, released when dropped
+pub struct ConfigLock {
+    _file: File,
+}
 
 pub struct ConfigManager {
     config_path: PathBuf,
+    // Status and shell history
+    data_dir: PathBuf,
+    // Downloaded data that can be refetched at any time, e.g. the release feed
+    cache_dir: PathBuf,
+    // Set for the system-wide config; commands that need to write fail
+    read_only: bool,
 }
 
 impl ConfigManager {
+    // Locate the config file: `--config`/WRTCLI_CONFIG, then the selected
+    // profile, then the top-level config
     pub fn new() -> Result<Self> {
-        let config_dir = dirs::home_dir()
-            .context("Could not find home directory")?
+        Self::locate(config_override(), &BaseDirs::from_env())
+    }
+
+    fn locate(config_override: Option<PathBuf>, base: &BaseDirs) -> Result<Self> {
+        if let Some(path) = config_override {
+            return Self::from_path(path);
+        }
+
+        let root = Self::root_in(base)?;
+        match root.active_profile()? {
+            Some(profile) => root.profile(&profile),
+            None => Ok(root),
+        }
+    }
+
+    // The top-level config, ignoring profiles: an existing ~/.wrtcli layout,
+    // then the platform's standard directories. Without a home directory,
+    // /etc/wrtcli/config.toml (read-only) or the working directory is used.
+    pub fn root() -> Result<Self> {
+        Self::root_in(&BaseDirs::from_env())
+    }
+
+    fn root_in(base: &BaseDirs) -> Result<Self> {
+        if let Some(legacy) = base.legacy_dir().map(|dir| config_file_in(&dir)).filter(|path| path.exists()) {
+            return Self::from_path(legacy);
+        }
+
+        match Self::platform_default(base) {
+            Err(_) if base.home.is_none() => Self::without_home(Path::new(SYSTEM_CONFIG)),
+            result => result,
+        }
+    }
+
+    fn without_home(system_config: &Path) -> Result<Self> {
+        let system = system_config.to_path_buf();
+        if system.exists() {
+            return Ok(Self {
+                data_dir: system.parent().unwrap_or(Path::new("/")).to_path_buf(),
+                cache_dir: std::env::temp_dir().join(APP_DIR),
+                config_path: system,
+                read_only: true,
+            });
+        }
+
+        let dir = std::env::current_dir()
+            .context("No home directory found and the working directory is unavailable; pass --config")?
             .join(".wrtcli");
-        
-        fs::create_dir_all(&config_dir)?;
-        
+        NO_HOME_WARNING.call_once(|| {
+            eprintln!("⚠️  No home directory found; using {} (pass --config to choose another location)", dir.display());
+        });
+        Self::from_path(dir.join(CONFIG_FILE))
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!(
+                "{} is the read-only system config (no home directory is available); \
+                 pass --config or set WRTCLI_CONFIG to use a writable file",
+                self.config_path.display()
+            );
+        }
+        Ok(())
+    }
+
+    // Use the given config file; history and cache files are kept next to it
+    pub fn from_path(config_path: PathBuf) -> Result<Self> {
+        let config_path = std::path::absolute(&config_path)
+            .context(format!("Invalid config path {}", config_path.display()))?;
+        let config_dir = config_path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        create_private_dir(&config_dir)?;
+
         Ok(Self {
-            config_path: config_dir.join("config.toml"),
+            cache_dir: config_dir.join("cache"),
+            data_dir: config_dir,
+            config_path,
+            read_only: false,
         })
     }
 
+    // XDG_CONFIG_HOME/XDG_DATA_HOME/XDG_CACHE_HOME (or their defaults) on
+    // Linux, and the conventional application directories on macOS and Windows
+    fn platform_default(base: &BaseDirs) -> Result<Self> {
+        let config_dir = base
+            .config
+            .as_ref()
+            .context("Could not find the user config directory")?
+            .join(APP_DIR);
+        let data_dir = base
+            .data
+            .as_ref()
+            .context("Could not find the user data directory")?
+            .join(APP_DIR);
+        let cache_dir = base
+            .cache
+            .as_ref()
+            .context("Could not find the user cache directory")?
+            .join(APP_DIR);
+
+        create_private_dir(&config_dir)?;
+
+        Ok(Self {
+            config_path: config_file_in(&config_dir),
+            data_dir,
+            cache_dir,
+            read_only: false,
+        })
+    }
+
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    fn profiles_dir(&self) -> PathBuf {
+        self.config_dir().join(PROFILES_DIR)
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.config_dir().join(STATE_FILE)
+    }
+
+    fn profile_config_path(&self, name: &str) -> Result<PathBuf> {
+        if !is_safe_name(name) || name == DEFAULT_PROFILE {
+            bail!("Invalid profile name '{}'", name);
+        }
+        Ok(config_file_in(&self.profiles_dir().join(name)))
+    }
+
+    // The config of an existing profile under this (top-level) config's
+    // directory; it keeps its history, cache and backup next to it
+    pub fn profile(&self, name: &str) -> Result<Self> {
+        let path = self.profile_config_path(name)?;
+        if !path.exists() {
+            bail!("Profile '{}' does not exist; create it with 'wrtcli profile create {}'", name, name);
+        }
+        Self::from_path(path)
+    }
+
+    // The profile in use: `--profile`/WRTCLI_PROFILE, then the one recorded
+    // by `profile use`. `None` means the top-level config.
+    pub fn active_profile(&self) -> Result<Option<String>> {
+        let profile = match PROFILE_OVERRIDE.lock().unwrap().clone() {
+            Some(profile) => Some(profile),
+            None => self.load_state()?.profile,
+        };
+        Ok(profile.filter(|profile| profile != DEFAULT_PROFILE))
+    }
+
+    fn load_state(&self) -> Result<State> {
+        let path = self.state_path();
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let content = fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).context(format!("Failed to parse {}", path.display()))
+    }
+
+    fn save_state(&self, state: &State) -> Result<()> {
+        self.ensure_writable()?;
+        let path = self.state_path();
+        let mut file = open_private(&path, false).context(format!("Failed to write {}", path.display()))?;
+        file.write_all(toml::to_string_pretty(state)?.as_bytes())?;
+        Ok(())
+    }
+
+    // Rewrite the config in another format next to the current file (e.g.
+    // config.toml -> config.json), keeping it encrypted if it was, and remove
+    // the old file. Returns the new path.
+    pub fn convert_format(&self, to: ConfigFormat) -> Result<PathBuf> {
+        let from = self.format();
+        if from == to {
+            bail!("{} is already in {} format", self.config_path.display(), from.extension());
+        }
+        if !self.config_path.exists() {
+            bail!("Nothing to convert: {} does not exist", self.config_path.display());
+        }
+
+        let _lock = self.lock()?;
+        let config = self.load_config()?;
+        let converted = Self {
+            config_path: self.config_path.with_extension(to.extension()),
+            data_dir: self.data_dir.clone(),
+            cache_dir: self.cache_dir.clone(),
+            read_only: false,
+        };
+        if converted.config_path.exists() {
+            bail!("{} already exists; remove it first", converted.config_path.display());
+        }
+
+        converted.write_config(&config, self.is_encrypted())?;
+        fs::remove_file(&self.config_path)
+            .context(format!("Failed to remove {}", self.config_path.display()))?;
+        Ok(converted.config_path)
+    }
+
+    // Names of the profiles under this (top-level) config's directory
+    pub fn list_profiles(&self) -> Result<Vec<String>> {
+        let dir = self.profiles_dir();
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut profiles = Vec::new();
+        for entry in fs::read_dir(&dir).context(format!("Failed to read {}", dir.display()))? {
+            let entry = entry?;
+            if config_file_in(&entry.path()).exists() {
+                profiles.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        profiles.sort();
+        Ok(profiles)
+    }
+
+    pub fn create_profile(&self, name: &str) -> Result<Self> {
+        self.ensure_writable()?;
+        let path = self.profile_config_path(name)?;
+        if path.exists() {
+            bail!("Profile '{}' already exists", name);
+        }
+        create_private_dir(&self.profiles_dir())?;
+        let profile = Self::from_path(path)?;
+        profile.save_config(&Config::new())?;
+        Ok(profile)
+    }
+
+    // Delete a profile's directory. Unless `force` is set, only profiles
+    // without devices are removed.
+    pub fn remove_profile(&self, name: &str, force: bool) -> Result<()> {
+        let profile = self.profile(name)?;
+        let _lock = profile.lock()?;
+        let devices = profile.load_config()?.devices.len();
+        if devices > 0 && !force {
+            bail!("Profile '{}' has {} device(s); pass --force to remove it anyway", name, devices);
+        }
+
+        let dir = profile.config_dir().to_path_buf();
+        drop(_lock);
+        fs::remove_dir_all(&dir).context(format!("Failed to remove {}", dir.display()))?;
+
+        let mut state = self.load_state()?;
+        if state.profile.as_deref() == Some(name) {
+            state.profile = None;
+            self.save_state(&state)?;
+        }
+        Ok(())
+    }
+
+    // Record the profile used when no `--profile` is given
+    pub fn use_profile(&self, name: &str) -> Result<()> {
+        let profile = if name == DEFAULT_PROFILE {
+            None
+        } else {
+            self.profile(name)?;
+            Some(name.to_string())
+        };
+        self.save_state(&State { profile })
+    }
+
+    pub fn config_dir(&self) -> &Path {
+        self.config_path.parent().unwrap_or(Path::new("."))
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    pub fn history_dir(&self) -> PathBuf {
+        self.data_dir.join("history")
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone()
+    }
+
+    // Configuration backups downloaded from devices, e.g. before a sysupgrade:
+    // the `backup_dir` setting (or its flag) when given, otherwise `backups/`
+    // in the data directory
+    pub fn device_backups_dir(&self, backup_dir: Option<&Path>) -> PathBuf {
+        match backup_dir {
+            Some(dir) => dir.to_path_buf(),
+            None => self.data_dir.join("backups"),
+        }
+    }
+
+    // A new path for a backup of `device_name`'s configuration, named
+    // `<device>_<timestamp>_<method>.<extension>` so that archives copied
+    // elsewhere still tell which router they came from. Backups hold the
+    // device's secrets, so a directory created for them is private.
+    pub fn new_device_backup_path(
+        &self,
+        backup_dir: Option<&Path>,
+        device_name: &str,
+        method: &str,
+        extension: &str,
+    ) -> Result<PathBuf> {
+        let dir = self.device_backups_dir(backup_dir);
+        if backup_dir.is_none() {
+            self.ensure_writable()?;
+            create_private_dir(&self.data_dir)?;
+        }
+        create_private_dir(&dir)?;
+        let timestamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+        Ok(dir.join(format!("{}_{}_{}.{}", file_name_part(device_name), timestamp, method, extension)))
+    }
+
+    // SSH host keys of the devices, in OpenSSH known_hosts format
+    pub fn known_hosts_path(&self) -> PathBuf {
+        self.data_dir.join("known_hosts")
+    }
+
+    pub fn shell_history_path(&self) -> PathBuf {
+        self.data_dir.join("shell_history")
+    }
+
+    fn history_path(&self, device_name: &str) -> PathBuf {
+        self.history_dir().join(format!("{}.jsonl", device_name))
+    }
+
+    // Append a status sample to the device's history, rotating the file to
+    // `<device>.jsonl.1` once it grows past MAX_HISTORY_BYTES
+    pub fn append_history(&self, device_name: &str, record: &StatusRecord) -> Result<()> {
+        self.ensure_writable()?;
+        create_private_dir(&self.data_dir)?;
+        create_private_dir(&self.history_dir())?;
+        let path = self.history_path(device_name);
+
+        if fs::metadata(&path).map(|m| m.len() >= MAX_HISTORY_BYTES).unwrap_or(false) {
+            fs::rename(&path, path.with_extension("jsonl.1"))
+                .context("Failed to rotate history file")?;
+        }
+
+        let mut file = open_private(&path, true)
+            .context("Failed to open history file")?;
+        writeln!(file, "{}", serde_json::to_string(record)?)
+            .context("Failed to write history file")?;
+
+        Ok(())
+    }
+
+    // Load all recorded samples for a device, oldest first. Lines that fail
+    // to parse (e.g. from an interrupted write) are skipped.
+    pub fn load_history(&self, device_name: &str) -> Result<Vec<StatusRecord>> {
+        let current = self.history_path(device_name);
+        let rotated = current.with_extension("jsonl.1");
+
+        let mut records = Vec::new();
+        for path in [rotated, current] {
+            if !path.exists() {
+                continue;
+            }
+            let content = fs::read_to_string(&path)
+                .context(format!("Failed to read {}", path.display()))?;
+            records.extend(content.lines().filter_map(|line| serde_json::from_str(line).ok()));
+        }
+
+        Ok(records)
+    }
+
     pub fn load_config(&self) -> Result<Config> {
         if !self.config_path.exists() {
             return Ok(Config::new());
         }
 
-        let content = fs::read_to_string(&self.config_path)
+        tracing::debug!("Loading config from {}", self.config_path.display());
+        let data = fs::read(&self.config_path)
             .context("Failed to read config file")?;
+        if data.is_empty() {
+            bail!("Config file {} is empty.{}", self.config_path.display(), self.restore_hint());
+        }
+        let data = if encryption::is_encrypted(&data) {
+            encryption::decrypt(&data)?
+        } else {
+            data
+        };
+        let content = String::from_utf8(data)
+            .context(format!("Config file is not valid UTF-8.{}", self.restore_hint()))?;
         
-        toml::from_str(&content)
-            .context("Failed to parse config file")
+        self.format()
+            .parse(&content)
+            .context(format!("Failed to parse config file.{}", self.restore_hint()))
+    }
+
+    pub fn format(&self) -> ConfigFormat {
+        ConfigFormat::of(&self.config_path)
+    }
+
+    // Copy of the previous config, kept by every save
+    pub fn backup_path(&self) -> PathBuf {
+        let mut name = self.config_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".bak");
+        self.config_path.with_file_name(name)
+    }
+
+    fn restore_hint(&self) -> String {
+        let backup = self.backup_path();
+        if !backup.exists() {
+            return String::new();
+        }
+        format!(
+            " The previous version is saved in {}; restore it with: cp '{}' '{}'",
+            backup.display(),
+            backup.display(),
+            self.config_path.display()
+        )
     }
 
+    // Whether the config file on disk was written by `config encrypt`
+    pub fn is_encrypted(&self) -> bool {
+        let mut header = [0u8; 16];
+        File::open(&self.config_path)
+            .and_then(|mut file| file.read(&mut header))
+            .map(|read| encryption::is_encrypted(&header[..read]))
+            .unwrap_or(false)
+    }
+
+    // Save the config, keeping it encrypted if it already was
     pub fn save_config(&self, config: &Config) -> Result<()> {
-        let content = toml::to_string_pretty(config)
+        self.write_config(config, self.is_encrypted())
+    }
+
+    fn write_config(&self, config: &Config, encrypt: bool) -> Result<()> {
+        self.ensure_writable()?;
+        tracing::debug!("Saving config to {}", self.config_path.display());
+        let content = self.format()
+            .serialize(config)
             .context("Failed to serialize config")?;
+        let data = if encrypt {
+            encryption::encrypt(content.as_bytes())?
+        } else {
+            content.into_bytes()
+        };
         
-        let mut file = File::create(&self.config_path)
-            .context("Failed to create config file")?;
-        
-        file.write_all(content.as_bytes())
-            .context("Failed to write config file")?;
-        
+        // Write a temporary file next to the config and rename it into
+        // place, so a crash or full disk never leaves a half-written config
+        let mut temp_name = self.config_path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(format!(".tmp-{}", std::process::id()));
+        let temp_path = self.config_path.with_file_name(temp_name);
+
+        let written = open_private(&temp_path, false)
+            .and_then(|mut file| {
+                file.write_all(&data)?;
+                file.sync_all()
+            })
+            .context("Failed to write config file");
+        if let Err(err) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        // The mode given to open() only applies to newly created files
+        set_private_mode(&temp_path, PRIVATE_FILE_MODE)?;
+
+        if encrypt && !self.is_encrypted() {
+            // Don't leave a plaintext copy behind when encrypting
+            if self.backup_path().exists() {
+                fs::remove_file(self.backup_path())
+                    .context("Failed to remove the unencrypted config backup")?;
+            }
+        } else if self.config_path.exists() {
+            fs::copy(&self.config_path, self.backup_path())
+                .context("Failed to back up the previous config file")?;
+            set_private_mode(&self.backup_path(), PRIVATE_FILE_MODE)?;
+        }
+        fs::rename(&temp_path, &self.config_path)
+            .context("Failed to replace config file")?;
+
+        // Make the rename itself durable
+        if let Ok(dir) = File::open(self.config_dir()) {
+            let _ = dir.sync_all();
+        }
+
         Ok(())
     }
 
+    // Files and directories holding credentials or device data, with the
+    // permissions they should have
+    fn private_paths(&self) -> Vec<(PathBuf, u32)> {
+        let mut paths = vec![
+            (self.config_path.clone(), PRIVATE_FILE_MODE),
+            (self.backup_path(), PRIVATE_FILE_MODE),
+            (self.shell_history_path(), PRIVATE_FILE_MODE),
+            (self.history_dir(), PRIVATE_DIR_MODE),
+        ];
+        for dir in [self.config_dir(), self.data_dir()] {
+            if is_app_dir(dir) {
+                paths.push((dir.to_path_buf(), PRIVATE_DIR_MODE));
+            }
+        }
+        if let Ok(entries) = fs::read_dir(self.history_dir()) {
+            paths.extend(entries.flatten().map(|entry| (entry.path(), PRIVATE_FILE_MODE)));
+        }
+
+        paths.sort();
+        paths.dedup();
+        paths.retain(|(path, _)| path.exists());
+        paths
+    }
+
+    // Private files and directories that other users can currently access
+    pub fn insecure_paths(&self) -> Vec<PathBuf> {
+        // The system config's permissions are up to the administrator
+        if self.read_only {
+            return Vec::new();
+        }
+        self.private_paths()
+            .into_iter()
+            .map(|(path, _)| path)
+            .filter(|path| is_shared(path))
+            .collect()
+    }
+
+    // Restrict every private file and directory to the user, returning the
+    // paths that were changed
+    pub fn fix_permissions(&self) -> Result<Vec<PathBuf>> {
+        self.ensure_writable()?;
+        let mut fixed = Vec::new();
+        for (path, mode) in self.private_paths() {
+            if is_shared(&path) {
+                set_private_mode(&path, mode)?;
+                fixed.push(path);
+            }
+        }
+        Ok(fixed)
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut name = self.config_path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        self.config_path.with_file_name(name)
+    }
+
+    // Take the config lock, waiting up to LOCK_TIMEOUT for another wrtcli
+    // process to finish its change. Readers don't need it because saves
+    // replace the file atomically.
+    pub fn lock(&self) -> Result<ConfigLock> {
+        self.ensure_writable()?;
+        let path = self.lock_path();
+        let file = open_private(&path, true)
+            .context(format!("Failed to open lock file {}", path.display()))?;
+
+        let started = Instant::now();
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(ConfigLock { _file: file }),
+                Err(fs::TryLockError::WouldBlock) if started.elapsed() < LOCK_TIMEOUT => {
+                    thread::sleep(LOCK_RETRY_INTERVAL);
+                }
+                Err(fs::TryLockError::WouldBlock) => bail!(
+                    "Another wrtcli process holds the lock on {} (waited {} seconds); try again once it finishes",
+                    self.config_path.display(),
+                    LOCK_TIMEOUT.as_secs()
+                ),
+                Err(fs::TryLockError::Error(err)) => {
+                    return Err(err).context(format!("Failed to lock {}", path.display()));
+                }
+            }
+        }
+    }
+
+    // Load, modify and save the config while holding the lock, so that
+    // concurrent invocations don't overwrite each other's changes
+    pub fn update_config(&self, update: impl FnOnce(&mut Config) -> Result<()>) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut config = self.load_config()?;
+        update(&mut config)?;
+        self.save_config(&config)
+    }
+
+    // Merge `devices` (e.g. from another machine's config) into this config.
+    // Everything is validated before anything is written, and all changes are
+    // saved at once; with `dry_run` nothing is saved at all.
+    pub fn import_devices(
+        &self,
+        devices: HashMap<String, Device>,
+        on_conflict: ConflictPolicy,
+        dry_run: bool,
+    ) -> Result<Vec<ImportDecision>> {
+        let invalid: Vec<String> = devices
+            .iter()
+            .flat_map(|(key, device)| validate_device(key, device))
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(|issue| format!("[devices.{}] {}", issue.device.unwrap_or_default(), issue.problem))
+            .collect();
+        if !invalid.is_empty() {
+            bail!("Nothing was imported; fix these devices first:\n  {}", invalid.join("\n  "));
+        }
+
+        // A dry run changes nothing, so it also works on a read-only config
+        let _lock = if dry_run { None } else { Some(self.lock()?) };
+        let mut config = self.load_config()?;
+        let mut keys: Vec<&String> = devices.keys().collect();
+        keys.sort();
+
+        let mut decisions = Vec::new();
+        for key in keys {
+            let mut device = devices[key].clone();
+            let action = match config.devices.get(key) {
+                None => ImportAction::Added,
+                Some(existing) if *existing == device => ImportAction::Unchanged,
+                Some(_) => match on_conflict {
+                    ConflictPolicy::Skip => ImportAction::Skipped,
+                    ConflictPolicy::Overwrite => ImportAction::Overwritten,
+                    ConflictPolicy::Rename => {
+                        let renamed = (2..)
+                            .map(|n| format!("{}-{}", key, n))
+                            .find(|name| !config.devices.contains_key(name) && !devices.contains_key(name))
+                            .unwrap_or_default();
+                        device.name = renamed.clone();
+                        ImportAction::Renamed { to: renamed }
+                    }
+                },
+            };
+            match &action {
+                ImportAction::Added | ImportAction::Overwritten => {
+                    config.devices.insert(key.clone(), device);
+                }
+                ImportAction::Renamed { to } => {
+                    config.devices.insert(to.clone(), device);
+                }
+                ImportAction::Unchanged | ImportAction::Skipped => {}
+            }
+            decisions.push(ImportDecision { device: key.clone(), action });
+        }
+
+        let errors: Vec<String> = validate_config(&config)
+            .into_iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(|issue| issue.problem)
+            .collect();
+        if !errors.is_empty() {
+            bail!("Nothing was imported; the merged config would be invalid:\n  {}", errors.join("\n  "));
+        }
+
+        if !dry_run {
+            self.save_config(&config)?;
+        }
+        Ok(decisions)
+    }
+
+    pub fn encrypt_config(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        if self.is_encrypted() {
+            bail!("{} is already encrypted", self.config_path.display());
+        }
+
+        let config = self.load_config()?;
+        encryption::set_passphrase(&encryption::read_passphrase(true)?)?;
+        self.write_config(&config, true)
+    }
+
+    pub fn decrypt_config(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        if !self.is_encrypted() {
+            bail!("{} is not encrypted", self.config_path.display());
+        }
+
+        let config = self.load_config()?;
+        self.write_config(&config, false)
+    }
+
     pub fn add_device(
         &self,
         name: &str,
         ip: &str,
         user: &str,
         password: &str,
+        password_command: Option<&str>,
+        headers: HashMap<String, String>,
     ) -> Result<()> {
-        let mut config = self.load_config()?;
-        
-        let device = Device::new(
+        let mut device = Device::new(
             name.to_string(),
             ip.to_string(),
             user.to_string(),
             password.to_string(),
         );
+        device.password_command = password_command.map(str::to_string);
+        device.headers = headers;
+
+        if let Some(issue) = validate_device(name, &device)
+            .into_iter()
+            .find(|issue| issue.severity == Severity::Error)
+        {
+            bail!("Invalid device '{}': {} ({})", name, issue.problem, issue.fix);
+        }
         
-        config.add_device(device);
-        self.save_config(&config)?;
-        
-        Ok(())
+        self.update_config(|config| {
+            config.add_device(device);
+            Ok(())
+        })
     }
 
+    // Look up a device with its `env:`/`file:` credential references expanded
     pub fn get_device(&self, name: &str) -> Result<Option<Device>> {
         let config = self.load_config()?;
-        Ok(config.get_device(name).cloned())
+        config.get_device(name).cloned().map(expand_credentials).transpose()
+    }
+
+    // Look up a device by name, falling back to the configured default
+    pub fn resolve_device(&self, name: Option<&str>) -> Result<Device> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => self.load_config()?.default_device.context(
+                "No device specified. Pass a device name, use --device/WRTCLI_DEVICE, \
+                 or set a default with 'wrtcli config set-default <name>'",
+            )?,
+        };
+
+        self.get_device(&name)?
+            .context(format!("Device '{}' not found", name))
+    }
+
+    pub fn set_default_device(&self, name: &str) -> Result<()> {
+        self.update_config(|config| {
+            if config.get_device(name).is_none() {
+                bail!("Device '{}' not found", name);
+            }
+
+            config.default_device = Some(name.to_string());
+            Ok(())
+        })
+    }
+
+    // Set the HTTP header `key` stored with a device, or remove it when
+    // `value` is `None`
+    pub fn set_device_header(&self, name: &str, key: &str, value: Option<&str>) -> Result<()> {
+        self.update_config(|config| {
+            let device = config.devices.get_mut(name).context(format!("Device '{}' not found", name))?;
+            match value {
+                Some(value) => {
+                    device.headers.insert(key.to_string(), value.to_string());
+                }
+                None => {
+                    if device.headers.remove(key).is_none() {
+                        bail!("Device '{}' has no header '{}'", name, key);
+                    }
+                }
+            }
+            if let Some(issue) = validate_device(name, device)
+                .into_iter()
+                .find(|issue| issue.severity == Severity::Error)
+            {
+                bail!("Invalid device '{}': {} ({})", name, issue.problem, issue.fix);
+            }
+            Ok(())
+        })
     }
 
     pub fn get_all_devices(&self) -> Result<Vec<Device>> {
@@ -79,3 +946,717 @@ impl ConfigManager {
         Ok(config.devices.values().cloned().collect())
     }
 }
+
+// What `config migrate` did: each (from, to) move that was made, and the
+// entries still in ~/.wrtcli afterwards (when it couldn't be removed)
+pub struct Migration {
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    pub left_behind: Vec<PathBuf>,
+}
+
+// Move an existing ~/.wrtcli layout into the platform's standard
+// directories
+pub fn migrate_legacy_layout() -> Result<Migration> {
+    if CONFIG_OVERRIDE.lock().unwrap().is_some() {
+        bail!("'config migrate' moves the default ~/.wrtcli layout and can't be combined with --config/WRTCLI_CONFIG");
+    }
+    migrate_legacy_layout_in(&BaseDirs::from_env())
+}
+
+fn migrate_legacy_layout_in(base: &BaseDirs) -> Result<Migration> {
+    let legacy = base.legacy_dir().context("Could not find home directory")?;
+    let legacy_file = config_file_in(&legacy);
+    if !legacy_file.exists() {
+        bail!("Nothing to migrate: {} does not exist", legacy_file.display());
+    }
+
+    let mut target = ConfigManager::platform_default(base)?;
+    // Keep the legacy file's format
+    target.config_path = target.config_dir().join(legacy_file.file_name().unwrap_or_default());
+    if target.config_path.exists() {
+        bail!(
+            "{} already exists; remove it or merge it by hand before migrating",
+            target.config_path.display()
+        );
+    }
+
+    // Everything in ~/.wrtcli lives next to the config file
+    let old = ConfigManager::from_path(legacy_file.clone())?;
+    let _lock = old.lock()?;
+    let moves = [
+        (legacy_file.clone(), target.config_path.clone()),
+        (old.backup_path(), target.backup_path()),
+        (old.history_dir(), target.history_dir()),
+        (old.shell_history_path(), target.shell_history_path()),
+        (old.known_hosts_path(), target.known_hosts_path()),
+        (old.device_backups_dir(None), target.device_backups_dir(None)),
+        (old.cache_dir(), target.cache_dir()),
+        (old.profiles_dir(), target.profiles_dir()),
+        (old.state_path(), target.state_path()),
+    ];
+
+    let mut moved = Vec::new();
+    for (from, to) in moves {
+        if !from.exists() || to.exists() {
+            continue;
+        }
+        if let Some(parent) = to.parent() {
+            create_private_dir(parent)?;
+        }
+        fs::rename(&from, &to)
+            .context(format!("Failed to move {} to {}", from.display(), to.display()))?;
+        moved.push((from, to));
+    }
+
+    // The lock only guards the legacy config, which is gone now
+    drop(_lock);
+    let _ = fs::remove_file(old.lock_path());
+
+    // Only remove ~/.wrtcli when nothing unexpected was left behind
+    let mut left_behind: Vec<PathBuf> = fs::read_dir(&legacy)
+        .context(format!("Failed to read {}", legacy.display()))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<_>>()?;
+    left_behind.sort();
+    if left_behind.is_empty() {
+        fs::remove_dir(&legacy).context(format!("Failed to remove {}", legacy.display()))?;
+    }
+
+    Ok(Migration { moved, left_behind })
+}
+
+// Prefixes that make a stored credential a reference, and the one that
+// escapes them
+const REFERENCE_PREFIXES: &[&str] = &["env:", "file:", "raw:"];
+const RAW_PREFIX: &str = "raw:";
+
+// A credential given as `value` stored so it is used literally, even when it
+// happens to start with `env:`, `file:` or `raw:`
+pub fn literal_credential(value: &str) -> String {
+    if REFERENCE_PREFIXES.iter().any(|prefix| value.starts_with(prefix)) {
+        format!("{}{}", RAW_PREFIX, value)
+    } else {
+        value.to_string()
+    }
+}
+
+// Expand a credential given as `env:VAR` (an environment variable) or
+// `file:/path` (a file, e.g. a secret mounted by an orchestrator, with
+// trailing newlines trimmed). `raw:` is stripped and the rest used as is, so
+// a literal value can start with one of these prefixes. Anything else is used
+// literally.
+fn expand_reference(device: &str, field: &str, value: &str) -> Result<String> {
+    if let Some(literal) = value.strip_prefix(RAW_PREFIX) {
+        return Ok(literal.to_string());
+    }
+    if let Some(var) = value.strip_prefix("env:") {
+        return std::env::var(var).ok().context(format!(
+            "Device '{}' takes its {} from environment variable {}, which is not set",
+            device, field, var
+        ));
+    }
+    if let Some(path) = value.strip_prefix("file:") {
+        let content = fs::read_to_string(path).context(format!(
+            "Device '{}' takes its {} from {}, which can't be read",
+            device, field, path
+        ))?;
+        return Ok(content.trim_end_matches(['\r', '\n']).to_string());
+    }
+    Ok(value.to_string())
+}
+
+// Replace `env:`/`file:` references in a device's user and password with
+// their values, and fill in empty credentials from ~/.netrc. Only done on
+// devices about to be used, never on ones that are saved back to the config.
+pub fn expand_credentials(mut device: Device) -> Result<Device> {
+    device.user = expand_reference(&device.name, "user", &device.user)?;
+    device.password = Secret::new(expand_reference(&device.name, "password", device.password.expose())?);
+    crate::logging::register_secret(device.password.expose());
+
+    let needs_password = device.password.is_empty() && device.password_command.is_none();
+    if device.user.is_empty() || needs_password {
+        if let Some(entry) = netrc::lookup(&device.ip) {
+            // As with curl, a password only applies to the login it is listed with
+            let login_matches = device.user.is_empty() || entry.login.as_deref().is_none_or(|login| login == device.user);
+            if device.user.is_empty() {
+                device.user = entry.login.unwrap_or_default();
+            }
+            if needs_password && login_matches {
+                device.password = Secret::new(entry.password.unwrap_or_default());
+            }
+        }
+    }
+    Ok(device)
+}
+
+// Ask for a secret without echoing it, typed twice with `confirm`. When
+// stdin isn't a terminal one line is read from it instead, so scripts can
+// pipe the secret in.
+pub fn read_secret(prompt: &str, confirm: bool) -> Result<String> {
+    if !std::io::stdin().is_terminal() {
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line)? == 0 {
+            bail!("No {} given on stdin", prompt.to_lowercase());
+        }
+        return Ok(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    let secret = rpassword::prompt_password(format!("{}: ", prompt))?;
+    if confirm && rpassword::prompt_password(format!("Confirm {}: ", prompt.to_lowercase()))? != secret {
+        bail!("{}s do not match", prompt);
+    }
+    Ok(secret)
+}
+
+// Resolve the password used to log in to a device. When `password_command`
+// is set it is run through the shell and its trimmed stdout is used; the
+// resolved secret is never written back to the config.
+pub fn resolve_password(device: &Device) -> Result<String> {
+    let Some(command) = &device.password_command else {
+        return Ok(device.password.expose().to_string());
+    };
+
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .context(format!("Failed to run password command for device '{}'", device.name))?;
+
+    if !output.status.success() {
+        bail!(
+            "Password command for device '{}' exited with {}",
+            device.name,
+            output.status
+        );
+    }
+
+    let password = String::from_utf8(output.stdout)
+        .context("Password command produced non UTF-8 output")?
+        .trim()
+        .to_string();
+    crate::logging::register_secret(&password);
+    if password.is_empty() {
+        bail!("Password command for device '{}' produced no output", device.name);
+    }
+
+    Ok(password)
+}
+
+// What `import_devices` did with one imported device
+#[derive(Serialize)]
+pub struct ImportDecision {
+    pub device: String,
+    #[serde(flatten)]
+    pub action: ImportAction,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case", tag = "action")]
+pub enum ImportAction {
+    Added,
+    // Already present with identical settings
+    Unchanged,
+    Skipped,
+    Overwritten,
+    Renamed { to: String },
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// A problem found in a loaded config that parsing alone doesn't catch
+#[derive(Serialize)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub device: Option<String>,
+    pub problem: String,
+    pub fix: String,
+}
+
+// Check that an address is an IP, `IP:port`, or a plausible hostname with
+// an optional port, i.e. something that can go into the ubus URL
+fn is_valid_address(address: &str) -> bool {
+    if address.parse::<IpAddr>().is_ok() || address.parse::<SocketAddr>().is_ok() {
+        return true;
+    }
+
+    let host = match address.rsplit_once(':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        Some(_) => return false,
+        None => address,
+    };
+
+    !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+// `name` with every character that is_safe_name would reject replaced by '_'
+fn file_name_part(name: &str) -> String {
+    let part: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+        .collect();
+    part.trim_start_matches('.').to_string()
+}
+
+// Device names are used in file names (e.g. status history), so keep them
+// to characters that are safe on every platform
+fn is_safe_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+// Rules for a single device entry, applied both when a device is added and
+// when the whole config is validated
+pub fn validate_device(key: &str, device: &Device) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let mut issue = |severity, problem: String, fix: String| {
+        issues.push(ConfigIssue { severity, device: Some(key.to_string()), problem, fix });
+    };
+
+    if !is_safe_name(&device.name) {
+        issue(
+            Severity::Error,
+            format!("name '{}' can't be used in file names", device.name),
+            "use only letters, digits, '-', '_' and '.', not starting with '.'".to_string(),
+        );
+    }
+
+    if device.ip.trim().is_empty() {
+        issue(
+            Severity::Error,
+            "ip is empty".to_string(),
+            "set ip to the device's address, e.g. ip = \"192.168.1.1\"".to_string(),
+        );
+    } else if !is_valid_address(&device.ip) {
+        issue(
+            Severity::Error,
+            format!("ip '{}' is not a valid address or hostname", device.ip),
+            "use an IP address or hostname, optionally followed by :port".to_string(),
+        );
+    }
+
+    // Empty credentials may come from ~/.netrc when the device is used
+    if device.user.trim().is_empty() {
+        issue(
+            Severity::Warning,
+            "user is empty".to_string(),
+            "set user to the RPC login, e.g. user = \"root\", or add a login for the device to ~/.netrc".to_string(),
+        );
+    }
+
+    let mut header_keys: Vec<&String> = device.headers.keys().collect();
+    header_keys.sort();
+    for key in header_keys {
+        if reqwest::header::HeaderName::from_bytes(key.as_bytes()).is_err() {
+            issue(
+                Severity::Error,
+                format!("header name '{}' is not a valid HTTP header name", key),
+                "use letters, digits and '-', e.g. X-Proxy-Token".to_string(),
+            );
+        } else if reqwest::header::HeaderValue::from_str(&device.headers[key]).is_err() {
+            issue(
+                Severity::Error,
+                format!("header '{}' has a value that can't be sent in HTTP", key),
+                "remove control characters and non-ASCII text from the value".to_string(),
+            );
+        }
+    }
+
+    if device.password.is_empty() && device.password_command.is_none() {
+        issue(
+            Severity::Warning,
+            "password is empty and no password_command is set".to_string(),
+            "set password or password_command (or a ~/.netrc entry) unless the device really has no password".to_string(),
+        );
+    }
+
+    issues
+}
+
+pub fn validate_config(config: &Config) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let mut keys: Vec<&String> = config.devices.keys().collect();
+    keys.sort();
+
+    let mut seen_names: HashMap<&str, &str> = HashMap::new();
+    let mut seen_addresses: HashMap<&str, &str> = HashMap::new();
+    for key in keys {
+        let device = &config.devices[key];
+
+        if device.name != *key {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                device: Some(key.clone()),
+                problem: format!("name field '{}' does not match its table key '{}'", device.name, key),
+                fix: format!("set name = \"{}\" under [devices.{}]", key, key),
+            });
+        }
+
+        if let Some(other) = seen_names.insert(&device.name, key) {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                device: Some(key.clone()),
+                problem: format!("duplicate device name '{}' (also used by [devices.{}])", device.name, other),
+                fix: "give each device a unique name".to_string(),
+            });
+        }
+
+        if let Some(other) = seen_addresses.insert(device.ip.trim(), key) {
+            issues.push(ConfigIssue {
+                severity: Severity::Warning,
+                device: Some(key.clone()),
+                problem: format!("ip '{}' is also used by [devices.{}]", device.ip, other),
+                fix: "remove the duplicate entry unless both names are meant to reach the same device".to_string(),
+            });
+        }
+
+        issues.extend(validate_device(key, device));
+    }
+
+    if let Some(default) = &config.default_device {
+        if !config.devices.contains_key(default) {
+            issues.push(ConfigIssue {
+                severity: Severity::Error,
+                device: None,
+                problem: format!("default_device '{}' is not a registered device", default),
+                fix: "run 'wrtcli config set-default <name>' or remove default_device".to_string(),
+            });
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    // A fresh, empty directory for one test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wrtcli-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Base directories for `home` with the given XDG variables set
+    fn xdg_dirs(home: &Path, vars: &[(&str, &str)]) -> BaseDirs {
+        let vars: HashMap<String, OsString> =
+            vars.iter().map(|(name, value)| (name.to_string(), OsString::from(value))).collect();
+        BaseDirs::xdg(Some(home.to_path_buf()), |name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn xdg_config_home_takes_precedence_over_home() {
+        let home = scratch_dir("xdg-over-home");
+        let xdg = home.join("xdg-config");
+        let base = xdg_dirs(&home, &[("XDG_CONFIG_HOME", xdg.to_str().unwrap())]);
+
+        let config = ConfigManager::locate(None, &base).unwrap();
+        assert_eq!(config.config_path(), xdg.join("wrtcli/config.toml"));
+        // Only the config directory was moved
+        assert_eq!(config.data_dir(), home.join(".local/share/wrtcli"));
+        assert_eq!(config.cache_dir(), home.join(".cache/wrtcli"));
+    }
+
+    #[test]
+    fn home_defaults_apply_without_xdg_variables() {
+        let home = scratch_dir("home-defaults");
+        let config = ConfigManager::locate(None, &xdg_dirs(&home, &[])).unwrap();
+        assert_eq!(config.config_path(), home.join(".config/wrtcli/config.toml"));
+        assert_eq!(config.data_dir(), home.join(".local/share/wrtcli"));
+    }
+
+    #[test]
+    fn relative_xdg_paths_are_ignored() {
+        let home = scratch_dir("xdg-relative");
+        let base = xdg_dirs(&home, &[("XDG_CONFIG_HOME", "relative/config")]);
+        let config = ConfigManager::locate(None, &base).unwrap();
+        assert_eq!(config.config_path(), home.join(".config/wrtcli/config.toml"));
+    }
+
+    #[test]
+    fn legacy_layout_wins_over_xdg() {
+        let home = scratch_dir("legacy");
+        fs::create_dir_all(home.join(".wrtcli")).unwrap();
+        fs::write(home.join(".wrtcli/config.toml"), "[devices]\n").unwrap();
+        let base = xdg_dirs(&home, &[("XDG_CONFIG_HOME", home.join("xdg").to_str().unwrap())]);
+
+        let config = ConfigManager::locate(None, &base).unwrap();
+        assert_eq!(config.config_path(), home.join(".wrtcli/config.toml"));
+    }
+
+    #[test]
+    fn config_flag_wins_over_everything() {
+        let home = scratch_dir("config-flag");
+        fs::create_dir_all(home.join(".wrtcli")).unwrap();
+        fs::write(home.join(".wrtcli/config.toml"), "[devices]\n").unwrap();
+        let base = xdg_dirs(&home, &[("XDG_CONFIG_HOME", home.join("xdg").to_str().unwrap())]);
+        let chosen = home.join("ci/wrtcli.json");
+
+        let config = ConfigManager::locate(Some(chosen.clone()), &base).unwrap();
+        assert_eq!(config.config_path(), chosen);
+        assert_eq!(config.format(), ConfigFormat::Json);
+        // History and cache are kept next to the chosen file
+        assert_eq!(config.data_dir(), home.join("ci"));
+    }
+
+    #[test]
+    fn without_home_uses_the_system_config_read_only() {
+        let dir = scratch_dir("system-config");
+        let system = dir.join("config.toml");
+        fs::write(&system, "[devices]\n").unwrap();
+
+        let config = ConfigManager::without_home(&system).unwrap();
+        assert_eq!(config.config_path(), system);
+        assert!(config.is_read_only());
+        assert!(config.save_config(&Config::new()).is_err());
+    }
+
+    #[cfg(unix)]
+    fn mode(path: &Path) -> u32 {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn creates_private_files_and_directories() {
+        let config = ConfigManager::from_path(scratch_dir("permissions").join("wrtcli/config.toml")).unwrap();
+        config.save_config(&Config::new()).unwrap();
+        config.save_config(&Config::new()).unwrap();
+        let record =
+            StatusRecord { timestamp: chrono::Utc::now(), uptime: 1, load: 0.0, memory_total_kb: 1, memory_free_kb: 1 };
+        config.append_history("router1", &record).unwrap();
+        let backup = config.new_device_backup_path(None, "router1", "sysupgrade", "tar.gz").unwrap();
+        open_private(&backup, false).unwrap();
+
+        assert_eq!(mode(config.config_dir()), 0o700);
+        assert_eq!(mode(config.config_path()), 0o600);
+        assert_eq!(mode(&config.backup_path()), 0o600);
+        assert_eq!(mode(&config.history_dir()), 0o700);
+        assert_eq!(mode(&config.history_dir().join("router1.jsonl")), 0o600);
+        assert_eq!(mode(&config.device_backups_dir(None)), 0o700);
+        assert_eq!(mode(&backup), 0o600);
+        assert!(config.insecure_paths().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fix_permissions_restricts_shared_files() {
+        let config = ConfigManager::from_path(scratch_dir("fix-perms").join("wrtcli/config.toml")).unwrap();
+        config.save_config(&Config::new()).unwrap();
+        set_private_mode(config.config_path(), 0o644).unwrap();
+        assert_eq!(config.insecure_paths(), vec![config.config_path().to_path_buf()]);
+
+        assert_eq!(config.fix_permissions().unwrap(), vec![config.config_path().to_path_buf()]);
+        assert_eq!(mode(config.config_path()), 0o600);
+        assert!(config.insecure_paths().is_empty());
+    }
+
+    fn device(name: &str) -> Device {
+        Device::new(name.to_string(), "192.168.1.1".to_string(), "root".to_string(), "secret".to_string())
+    }
+
+    #[test]
+    fn failed_write_keeps_the_config_and_its_backup() {
+        let config = ConfigManager::from_path(scratch_dir("failed-write").join("config.toml")).unwrap();
+        let mut first = Config::new();
+        first.add_device(device("first"));
+        config.save_config(&first).unwrap();
+        let mut second = first.clone();
+        second.add_device(device("second"));
+        config.save_config(&second).unwrap();
+        let saved = fs::read_to_string(config.config_path()).unwrap();
+        let backup = fs::read_to_string(config.backup_path()).unwrap();
+
+        // A directory where the temporary file goes makes the write fail,
+        // even when the tests run as root
+        let mut temp_name = config.config_path().file_name().unwrap().to_os_string();
+        temp_name.push(format!(".tmp-{}", std::process::id()));
+        fs::create_dir(config.config_path().with_file_name(temp_name)).unwrap();
+        let mut third = second.clone();
+        third.add_device(device("third"));
+        assert!(config.save_config(&third).is_err());
+
+        assert_eq!(fs::read_to_string(config.config_path()).unwrap(), saved);
+        assert_eq!(fs::read_to_string(config.backup_path()).unwrap(), backup);
+        assert_eq!(config.load_config().unwrap().devices.len(), 2);
+    }
+
+    #[test]
+    fn dry_run_import_leaves_the_config_untouched() {
+        let config = ConfigManager::from_path(scratch_dir("import-dry-run").join("config.toml")).unwrap();
+        let mut existing = Config::new();
+        existing.add_device(device("first"));
+        config.save_config(&existing).unwrap();
+        let _ = fs::remove_file(config.lock_path());
+        let saved = fs::read_to_string(config.config_path()).unwrap();
+
+        let devices = HashMap::from([("second".to_string(), device("second"))]);
+        let decisions = config.import_devices(devices, ConflictPolicy::Skip, true).unwrap();
+        assert_eq!(decisions.len(), 1);
+        assert!(matches!(decisions[0].action, ImportAction::Added));
+
+        assert_eq!(fs::read_to_string(config.config_path()).unwrap(), saved);
+        assert!(!config.lock_path().exists());
+    }
+
+    #[test]
+    fn concurrent_updates_all_land() {
+        let path = scratch_dir("concurrent-add").join("config.toml");
+        let threads: Vec<_> = (0..8)
+            .map(|i| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    // Each thread opens its own lock file handle, like a
+                    // separate `wrtcli add` process would
+                    let config = ConfigManager::from_path(path).unwrap();
+                    config
+                        .update_config(|c| {
+                            c.add_device(device(&format!("router{}", i)));
+                            Ok(())
+                        })
+                        .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let config = ConfigManager::from_path(path).unwrap().load_config().unwrap();
+        assert_eq!(config.devices.len(), 8);
+        for i in 0..8 {
+            assert!(config.devices.contains_key(&format!("router{}", i)));
+        }
+    }
+
+    // A config with every optional field set
+    fn full_config() -> Config {
+        let mut router1 = device("router1");
+        router1.ip = "192.168.1.1:8080".to_string();
+        router1.password_command = Some("pass show routers/router1".to_string());
+        router1.headers.insert("X-Proxy-Token".to_string(), "abc123".to_string());
+        router1.headers.insert("Authorization".to_string(), "Bearer xyz".to_string());
+        let mut config = Config::new();
+        config.add_device(router1);
+        config.add_device(device("router2"));
+        config.default_device = Some("router1".to_string());
+        config.settings = crate::models::Settings {
+            connect_timeout: Some(5),
+            output: Some(crate::models::OutputFormat::Json),
+            concurrency: Some(4),
+            backup_dir: Some(PathBuf::from("/mnt/nas/routers")),
+            logging: crate::models::LoggingSettings {
+                level: Some(crate::models::LogLevel::Debug),
+                file: Some(PathBuf::from("/var/log/wrtcli.log")),
+            },
+        };
+        config
+    }
+
+    #[test]
+    fn raw_prefix_escapes_references() {
+        assert_eq!(expand_reference("gw", "password", "raw:env:HOME").unwrap(), "env:HOME");
+        assert_eq!(expand_reference("gw", "password", "raw:file:/etc/passwd").unwrap(), "file:/etc/passwd");
+        assert_eq!(expand_reference("gw", "password", "raw:raw:x").unwrap(), "raw:x");
+        assert_eq!(expand_reference("gw", "password", "plain").unwrap(), "plain");
+        assert!(expand_reference("gw", "password", "env:WRTCLI_TEST_SURELY_UNSET").is_err());
+
+        for value in ["env:HOME", "file:/x", "raw:y", "hunter2"] {
+            assert_eq!(expand_reference("gw", "password", &literal_credential(value)).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn toml_round_trip_keeps_every_field() {
+        let config = ConfigManager::from_path(scratch_dir("round-trip-toml").join("config.toml")).unwrap();
+        config.save_config(&full_config()).unwrap();
+        assert_eq!(config.load_config().unwrap(), full_config());
+    }
+
+    #[test]
+    fn json_round_trip_keeps_every_field() {
+        let config = ConfigManager::from_path(scratch_dir("round-trip-json").join("config.json")).unwrap();
+        assert_eq!(config.format(), ConfigFormat::Json);
+        config.save_config(&full_config()).unwrap();
+        assert!(fs::read_to_string(config.config_path()).unwrap().starts_with('{'));
+        assert_eq!(config.load_config().unwrap(), full_config());
+    }
+
+    #[test]
+    fn backups_go_to_the_configured_directory() {
+        let dir = scratch_dir("backup-dir");
+        let config = ConfigManager::from_path(dir.join("wrtcli/config.toml")).unwrap();
+
+        let default = config.new_device_backup_path(None, "router1", "sysupgrade", "tar.gz").unwrap();
+        assert_eq!(default.parent().unwrap(), config.data_dir().join("backups"));
+
+        let nas = dir.join("nas/routers");
+        let backup = config.new_device_backup_path(Some(&nas), "../we ird", "network", "uci").unwrap();
+        assert!(nas.is_dir());
+        assert_eq!(backup.parent().unwrap(), nas);
+        let name = backup.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("_we_ird_") && name.ends_with("_network.uci"), "{}", name);
+    }
+
+    #[test]
+    fn migration_moves_everything_next_to_the_legacy_config() {
+        let home = scratch_dir("migrate");
+        let legacy = home.join(".wrtcli");
+        for dir in ["history", "backups", "cache", "profiles/lab"] {
+            fs::create_dir_all(legacy.join(dir)).unwrap();
+        }
+        for file in [
+            "config.toml",
+            "config.toml.bak",
+            "config.toml.lock",
+            "known_hosts",
+            "shell_history",
+            "backups/router1_20250101-120000_sysupgrade.tar.gz",
+        ] {
+            fs::write(legacy.join(file), "x").unwrap();
+        }
+
+        let base = xdg_dirs(&home, &[]);
+        let migration = migrate_legacy_layout_in(&base).unwrap();
+        assert_eq!(migration.moved.len(), 8);
+        assert!(migration.left_behind.is_empty());
+        assert!(!legacy.exists());
+
+        let target = ConfigManager::locate(None, &base).unwrap();
+        assert!(target.config_path().exists());
+        assert!(target.backup_path().exists());
+        assert!(target.known_hosts_path().exists());
+        assert!(target.device_backups_dir(None).join("router1_20250101-120000_sysupgrade.tar.gz").exists());
+        assert!(target.profiles_dir().join("lab").exists());
+    }
+
+    #[test]
+    fn migration_reports_what_it_left_behind() {
+        let home = scratch_dir("migrate-leftovers");
+        let legacy = home.join(".wrtcli");
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(legacy.join("config.toml"), "").unwrap();
+        fs::write(legacy.join("notes.txt"), "x").unwrap();
+
+        let migration = migrate_legacy_layout_in(&xdg_dirs(&home, &[])).unwrap();
+        assert_eq!(migration.left_behind, [legacy.join("notes.txt")]);
+    }
+}
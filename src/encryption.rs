@@ -0,0 +1,111 @@
+use anyhow::{bail, Context, Result};
+use crate::config;
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::sync::Mutex;
+
+// Encrypted config files start with MAGIC, followed by the argon2 salt, the
+// XChaCha20-Poly1305 nonce and the ciphertext of the TOML document
+const MAGIC: &[u8] = b"WRTCLI-ENC1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
+const PASSPHRASE_ENV: &str = "WRTCLI_PASSPHRASE";
+
+// Key derived during this process, so that commands which load and save the
+// config several times only ask for the passphrase once
+static KEY_CACHE: Mutex<Option<DerivedKey>> = Mutex::new(None);
+
+#[derive(Clone)]
+struct DerivedKey {
+    salt: [u8; SALT_LEN],
+    key: [u8; KEY_LEN],
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+// Read the passphrase from WRTCLI_PASSPHRASE, or prompt for it without echo
+// (a line of stdin when it isn't a terminal). With `confirm` the passphrase
+// must be typed twice.
+pub fn read_passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = config::read_secret("Config passphrase", confirm)
+        .context(format!("Set {} to provide the passphrase non-interactively", PASSPHRASE_ENV))?;
+    if passphrase.is_empty() {
+        bail!("The passphrase must not be empty");
+    }
+
+    Ok(passphrase)
+}
+
+fn derive_key(passphrase: &str, salt: [u8; SALT_LEN]) -> Result<DerivedKey> {
+    let mut key = [0u8; KEY_LEN];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|err| anyhow::anyhow!("Failed to derive encryption key: {}", err))?;
+    Ok(DerivedKey { salt, key })
+}
+
+// Forget any cached key and derive a new one from `passphrase` with a fresh
+// salt; subsequent `encrypt` calls use it
+pub fn set_passphrase(passphrase: &str) -> Result<()> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    *KEY_CACHE.lock().unwrap() = Some(derive_key(passphrase, salt)?);
+    Ok(())
+}
+
+fn cached_key(salt: Option<&[u8]>) -> Option<DerivedKey> {
+    KEY_CACHE
+        .lock()
+        .unwrap()
+        .clone()
+        .filter(|cached| salt.is_none_or(|salt| cached.salt == salt))
+}
+
+pub fn encrypt(plaintext: &[u8]) -> Result<Vec<u8>> {
+    let derived = match cached_key(None) {
+        Some(derived) => derived,
+        None => {
+            set_passphrase(&read_passphrase(true)?)?;
+            cached_key(None).context("No encryption key available")?
+        }
+    };
+
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let cipher = XChaCha20Poly1305::new((&derived.key).into());
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt config"))?;
+
+    Ok([MAGIC, &derived.salt, &nonce, &ciphertext].concat())
+}
+
+pub fn decrypt(data: &[u8]) -> Result<Vec<u8>> {
+    let body = data.strip_prefix(MAGIC).context("Not an encrypted config file")?;
+    if body.len() < SALT_LEN + NONCE_LEN {
+        bail!("Encrypted config file is truncated");
+    }
+    let (salt, rest) = body.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let derived = match cached_key(Some(salt)) {
+        Some(derived) => derived,
+        None => derive_key(&read_passphrase(false)?, salt.try_into()?)?,
+    };
+    let cipher = XChaCha20Poly1305::new((&derived.key).into());
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase or corrupted config file"))?;
+
+    *KEY_CACHE.lock().unwrap() = Some(derived);
+    Ok(plaintext)
+}
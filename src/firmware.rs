@@ -0,0 +1,229 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Client, StatusCode, Url};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_FEED_URL: &str = "https://downloads.openwrt.org";
+const FEED_CACHE_TTL_SECS: u64 = 6 * 60 * 60;
+const FEED_TIMEOUT_SECS: u64 = 15;
+
+// The sysupgrade image a release provides for a board
+#[derive(Debug, Clone, Serialize)]
+pub struct ReleaseImage {
+    pub url: String,
+    pub sha256: String,
+}
+
+pub enum TargetSupport {
+    // The release has no build for the target, or none for the board
+    Unsupported,
+    // `None` when the board is unknown or its profile lists no sysupgrade image
+    Supported(Option<ReleaseImage>),
+}
+
+// The release feed for one invocation. Documents are kept in memory once
+// fetched (or read from the cache directory), so the devices checked by
+// `--all` share a single download of each index.
+pub struct Feed {
+    client: Client,
+    url: String,
+    cache_dir: PathBuf,
+    documents: Mutex<HashMap<String, Option<Value>>>,
+}
+
+fn is_fresh(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map(|age| age < Duration::from_secs(FEED_CACHE_TTL_SECS))
+        .unwrap_or(false)
+}
+
+// Fetch a JSON document from the release feed, reusing the copy cached at
+// `cache_path` while it is younger than FEED_CACHE_TTL_SECS. Returns `None`
+// when the server has no such document (HTTP 404).
+async fn fetch_cached(client: &Client, url: &str, cache_path: &Path) -> Result<Option<Value>> {
+    if is_fresh(cache_path) {
+        if let Some(cached) = fs::read_to_string(cache_path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+        {
+            return Ok(Some(cached));
+        }
+    }
+
+    let response = client.get(url).send().await.map_err(|err| feed_error(url, err))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        bail!("The release server answered HTTP {} for {}{}", response.status(), url, proxy_note(url));
+    }
+
+    let data = response
+        .json::<Value>()
+        .await
+        .context(format!("Invalid JSON from {}{}", url, proxy_note(url)))?;
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(cache_path, serde_json::to_string(&data)?).context("Failed to write feed cache")?;
+
+    Ok(Some(data))
+}
+
+// The proxy reqwest picks up from the environment for `url`, as the
+// variable name and the proxy's host (without any credentials)
+fn proxy_for(url: &str) -> Option<(&'static str, String)> {
+    let vars: &[&'static str] = if url.starts_with("http://") {
+        &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]
+    } else {
+        &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+    };
+    vars.iter().find_map(|var| {
+        let value = std::env::var(var).ok().filter(|value| !value.is_empty())?;
+        let host = Url::parse(&value)
+            .ok()
+            .and_then(|proxy| proxy.host_str().map(|host| match proxy.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host.to_string(),
+            }))
+            .unwrap_or(value);
+        Some((*var, host))
+    })
+}
+
+fn proxy_note(url: &str) -> String {
+    match proxy_for(url) {
+        Some((var, host)) => format!(" (through the proxy {} from {})", host, var),
+        None => String::new(),
+    }
+}
+
+// Errors reaching the release server name the server and any proxy in the
+// way, so they aren't mistaken for a problem with the device
+fn feed_error(url: &str, err: reqwest::Error) -> anyhow::Error {
+    let server = Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| url.to_string());
+    let hint = match proxy_for(url) {
+        Some((var, host)) => format!(
+            "the request went through the proxy {} from {}; check that the proxy is reachable and allows {}",
+            host, var, server
+        ),
+        None => format!(
+            "no proxy is configured; if this network needs one, set HTTPS_PROXY (or HTTP_PROXY), or pass --feed-url with a mirror of {}",
+            server
+        ),
+    };
+    anyhow::Error::new(err).context(format!("Failed to reach the OpenWrt release server {} ({}): {}", server, url, hint))
+}
+
+impl Feed {
+    pub fn new(url: &str, cache_dir: PathBuf) -> Result<Feed> {
+        let client = Client::builder()
+            .user_agent(concat!("wrtcli/", env!("CARGO_PKG_VERSION")))
+            .timeout(Duration::from_secs(FEED_TIMEOUT_SECS))
+            .build()?;
+        Ok(Feed {
+            client,
+            url: url.trim_end_matches('/').to_string(),
+            cache_dir,
+            documents: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // A document under the feed URL, cached on disk as `cache_name`
+    async fn fetch(&self, path: &str, cache_name: &str) -> Result<Option<Value>> {
+        let url = format!("{}/{}", self.url, path);
+        if let Some(document) = self.documents.lock().unwrap().get(&url) {
+            return Ok(document.clone());
+        }
+        let document = fetch_cached(&self.client, &url, &self.cache_dir.join(cache_name)).await?;
+        self.documents.lock().unwrap().insert(url, document.clone());
+        Ok(document)
+    }
+
+    // Latest stable release version advertised by the feed (e.g. "23.05.5")
+    pub async fn latest_stable(&self) -> Result<String> {
+        let versions = self
+            .fetch(".versions.json", "versions.json")
+            .await?
+            .context(format!("Release index not found at {}/.versions.json", self.url))?;
+
+        versions["stable_version"]
+            .as_str()
+            .map(str::to_string)
+            .context("Release index has no stable_version")
+    }
+
+    // Whether `version` was built for `target` (e.g. "ramips/mt7621") and,
+    // when `board_name` is given, the board's sysupgrade image. The board
+    // must be one of the target's profiles.
+    pub async fn target_support(&self, version: &str, target: &str, board_name: Option<&str>) -> Result<TargetSupport> {
+        let target_path = format!("releases/{}/targets/{}", version, target);
+        let cache_name = format!("profiles-{}-{}.json", version, target.replace('/', "-"));
+        let Some(profiles) = self.fetch(&format!("{}/profiles.json", target_path), &cache_name).await? else {
+            return Ok(TargetSupport::Unsupported);
+        };
+
+        let Some(board_name) = board_name else {
+            return Ok(TargetSupport::Supported(None));
+        };
+        // Profiles list the board names they run on; older indexes only have
+        // the profile id, which is the board name with ',' replaced by '_'
+        let profile = profiles["profiles"].as_object().and_then(|profiles| {
+            profiles
+                .values()
+                .find(|profile| {
+                    profile["supported_devices"]
+                        .as_array()
+                        .map(|devices| devices.iter().any(|d| d.as_str() == Some(board_name)))
+                        .unwrap_or(false)
+                })
+                .or_else(|| profiles.get(&board_name.replace(',', "_")))
+        });
+        let Some(profile) = profile else {
+            return Ok(TargetSupport::Unsupported);
+        };
+
+        let image = profile["images"].as_array().and_then(|images| {
+            images.iter().find(|image| image["type"].as_str() == Some("sysupgrade"))
+        });
+        let image = image.and_then(|image| {
+            Some(ReleaseImage {
+                url: format!("{}/{}/{}", self.url, target_path, image["name"].as_str()?),
+                sha256: image["sha256"].as_str()?.to_string(),
+            })
+        });
+        Ok(TargetSupport::Supported(image))
+    }
+}
+
+// Sort key for OpenWrt release versions: numeric components, then release
+// candidates before the final release. Returns `None` for snapshots and
+// anything else that isn't a tagged release.
+fn version_key(version: &str) -> Option<(Vec<u64>, u64)> {
+    let (numbers, rc) = match version.split_once("-rc") {
+        Some((numbers, rc)) => (numbers, rc.parse().ok()?),
+        None => (version, u64::MAX),
+    };
+    let numbers = numbers
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    Some((numbers, rc))
+}
+
+// Whether `candidate` is a newer release than `current`; `None` when either
+// version can't be compared (e.g. a SNAPSHOT build)
+pub fn is_newer(candidate: &str, current: &str) -> Option<bool> {
+    Some(version_key(candidate)? > version_key(current)?)
+}
@@ -0,0 +1,220 @@
+use crate::models::Device;
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+
+const SHELL: &str = "/bin/sh";
+
+// One line per LED in /sys/class/leds: "name|triggers|brightness|max". The
+// `-e` test skips the unexpanded glob when there are no LEDs (or no
+// /sys/class/leds at all).
+const LIST_SCRIPT: &str = r#"for led in /sys/class/leds/*; do
+  [ -e "$led" ] || continue
+  echo "${led##*/}|$(cat "$led/trigger")|$(cat "$led/brightness")|$(cat "$led/max_brightness")"
+done"#;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Led {
+    pub name: String,
+    pub trigger: String,
+    // Triggers the kernel offers for this LED
+    pub triggers: Vec<String>,
+    pub brightness: u32,
+    pub max_brightness: u32,
+}
+
+// Run a shell script through `file exec`; the session's ACLs must allow
+// executing /bin/sh
+async fn run_script(client: &Client, device: &Device, session: &str, script: &str) -> Result<String> {
+    let output = ubus::exec(client, device, session, SHELL, &["-c", script]).await?;
+    if output.code != 0 {
+        bail!("LED command on '{}' exited with {}: {}", device.name, output.code, output.stderr.trim());
+    }
+    Ok(output.stdout)
+}
+
+// The "trigger" file lists every trigger with the active one in brackets:
+// "none [default-on] heartbeat timer"
+fn parse_triggers(value: &str) -> (String, Vec<String>) {
+    let mut active = String::from("none");
+    let triggers = value
+        .split_whitespace()
+        .map(|trigger| match trigger.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+            Some(current) => {
+                active = current.to_string();
+                current.to_string()
+            }
+            None => trigger.to_string(),
+        })
+        .collect();
+    (active, triggers)
+}
+
+fn parse_leds(output: &str) -> Vec<Led> {
+    let mut leds: Vec<Led> = output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('|').collect();
+            let [name, triggers, brightness, max_brightness] = fields.as_slice() else {
+                return None;
+            };
+            let (trigger, triggers) = parse_triggers(triggers);
+            Some(Led {
+                name: name.to_string(),
+                trigger,
+                triggers,
+                brightness: brightness.trim().parse().unwrap_or(0),
+                max_brightness: max_brightness.trim().parse().unwrap_or(0),
+            })
+        })
+        .collect();
+    leds.sort_by(|a, b| a.name.cmp(&b.name));
+    leds
+}
+
+pub async fn list(client: &Client, device: &Device, session: &str) -> Result<Vec<Led>> {
+    Ok(parse_leds(&run_script(client, device, session, LIST_SCRIPT).await?))
+}
+
+// The LED called `name`, listing the valid names when there is none
+pub fn find<'a>(device: &Device, leds: &'a [Led], name: &str) -> Result<&'a Led> {
+    if leds.is_empty() {
+        bail!("'{}' has no LEDs in /sys/class/leds", device.name);
+    }
+    leds.iter().find(|led| led.name == name).with_context(|| {
+        let names: Vec<&str> = leds.iter().map(|led| led.name.as_str()).collect();
+        format!("'{}' has no LED '{}' (LEDs: {})", device.name, name, names.join(", "))
+    })
+}
+
+// Set the trigger first: switching triggers resets the brightness
+pub async fn set(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    led: &Led,
+    trigger: Option<&str>,
+    brightness: Option<u32>,
+) -> Result<()> {
+    let dir = shlex::try_quote(&format!("/sys/class/leds/{}", led.name))
+        .context("LED names must not contain NUL bytes")?
+        .into_owned();
+    let mut commands = Vec::new();
+    if let Some(trigger) = trigger {
+        if !led.triggers.iter().any(|t| t == trigger) {
+            bail!("{} has no trigger '{}' (triggers: {})", led.name, trigger, led.triggers.join(", "));
+        }
+        commands.push(format!("echo {} > {}/trigger", trigger, dir));
+    }
+    if let Some(brightness) = brightness {
+        if brightness > led.max_brightness {
+            bail!("{} takes a brightness from 0 to {}", led.name, led.max_brightness);
+        }
+        commands.push(format!("echo {} > {}/brightness", brightness, dir));
+    }
+    run_script(client, device, session, &commands.join(" && ")).await?;
+    Ok(())
+}
+
+// Save the setting in a `led` section of /etc/config/system, so the led
+// init script applies it at boot. The section for this LED's sysfs name is
+// updated, or a new "led_<name>" section added. `brightness` is saved as
+// well when given (it only applies with the none and default-on triggers).
+pub async fn persist(client: &Client, device: &Device, session: &str, led: &Led, brightness: Option<u32>) -> Result<()> {
+    // Without the existing sections, a second section for this LED could be
+    // added next to the first
+    let sections = ubus::call(client, device, session, "uci", "get", json!({ "config": "system", "type": "led" }))
+        .await
+        .context(format!("Failed to read the LED sections of the system config on {}", device.name))?;
+    let existing = sections["values"].as_object().and_then(|sections| {
+        sections
+            .iter()
+            .find(|(_, section)| section["sysfs"].as_str() == Some(&led.name))
+            .map(|(name, _)| name.clone())
+    });
+
+    let mut values = json!({ "sysfs": led.name, "trigger": led.trigger });
+    if let Some(brightness) = brightness {
+        values["default"] = json!(if brightness > 0 { "1" } else { "0" });
+        values["brightness"] = json!(brightness.to_string());
+    }
+    let saved = match existing {
+        Some(section) => {
+            ubus::call(
+                client,
+                device,
+                session,
+                "uci",
+                "set",
+                json!({ "config": "system", "section": section, "values": values }),
+            )
+            .await
+        }
+        None => {
+            let section: String = led
+                .name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            ubus::call(
+                client,
+                device,
+                session,
+                "uci",
+                "add",
+                json!({ "config": "system", "type": "led", "name": format!("led_{}", section), "values": values }),
+            )
+            .await
+        }
+    };
+    if let Err(err) = saved {
+        let _ = ubus::call(client, device, session, "uci", "revert", json!({ "config": "system" })).await;
+        return Err(err.context(format!("Failed to save {} in the system config of {}", led.name, device.name)));
+    }
+    ubus::call(client, device, session, "uci", "commit", json!({ "config": "system" }))
+        .await
+        .context(format!("Failed to commit the system config on {}", device.name))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_leds_gives_an_empty_list() {
+        assert!(parse_leds("").is_empty());
+        assert!(parse_leds("\n").is_empty());
+    }
+
+    #[test]
+    fn reads_the_active_trigger_from_the_brackets() {
+        let (active, triggers) = parse_triggers("none [default-on] heartbeat timer netdev");
+        assert_eq!(active, "default-on");
+        assert_eq!(triggers, vec!["none", "default-on", "heartbeat", "timer", "netdev"]);
+
+        // Without brackets (an unusual kernel), "none" is assumed
+        let (active, triggers) = parse_triggers("none timer");
+        assert_eq!(active, "none");
+        assert_eq!(triggers.len(), 2);
+    }
+
+    #[test]
+    fn parses_leds_sorted_by_name() {
+        let output = "\
+green:wlan|none timer [phy0tpt] phy0rx|0|255
+blue:power|[none] default-on heartbeat|1|1
+broken line
+amber:wan|none [netdev]|0|1
+";
+        let leds = parse_leds(output);
+        let names: Vec<&str> = leds.iter().map(|led| led.name.as_str()).collect();
+        assert_eq!(names, vec!["amber:wan", "blue:power", "green:wlan"]);
+        assert_eq!(leds[1].trigger, "none");
+        assert_eq!(leds[1].brightness, 1);
+        assert_eq!(leds[2].trigger, "phy0tpt");
+        assert_eq!(leds[2].max_brightness, 255);
+    }
+}
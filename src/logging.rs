@@ -0,0 +1,168 @@
+use crate::config;
+use crate::models::LogLevel;
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, Layer};
+
+// Rotate the log file once it reaches MAX_LOG_BYTES, keeping this many
+// older files (wrtcli.log.1 is the most recent)
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+const KEPT_LOG_FILES: usize = 3;
+const REDACTED: &str = "***";
+
+// Passwords and session tokens seen during this process. They are replaced
+// in every log line, wherever they end up in a message.
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+pub fn register_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap();
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+fn redact(line: &str) -> String {
+    let mut line = line.to_string();
+    for secret in SECRETS.read().unwrap().iter() {
+        line = line.replace(secret.as_str(), REDACTED);
+    }
+    line
+}
+
+fn level_filter(level: LogLevel) -> LevelFilter {
+    match level {
+        LogLevel::Off => LevelFilter::OFF,
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    }
+}
+
+// wrtcli's own messages at `level`; libraries (e.g. the HTTP client) are
+// very chatty, so only their warnings and errors are kept
+fn targets(level: LevelFilter) -> Targets {
+    Targets::new()
+        .with_default(level.min(LevelFilter::WARN))
+        .with_target(env!("CARGO_CRATE_NAME"), level)
+}
+
+// Set up logging for the process. Without a log file, messages at `level`
+// (default: warn) go to stderr. With one, the file gets `level` (default:
+// info) and stderr still gets warnings and errors.
+pub fn init(level: Option<LogLevel>, file: Option<&Path>) -> Result<()> {
+    let stderr_level = match file {
+        Some(_) => LevelFilter::WARN,
+        None => level.map(level_filter).unwrap_or(LevelFilter::WARN),
+    };
+    let stderr = fmt::layer()
+        .with_writer(Redacting(io::stderr))
+        .with_target(false)
+        .without_time()
+        .with_filter(targets(stderr_level));
+
+    let file = match file {
+        Some(path) => {
+            let writer = RotatingFile::open(path)?;
+            Some(
+                fmt::layer()
+                    .with_writer(Redacting(Mutex::new(writer)))
+                    .with_ansi(false)
+                    .with_filter(targets(level.map(level_filter).unwrap_or(LevelFilter::INFO))),
+            )
+        }
+        None => None,
+    };
+
+    // A second call (e.g. from the interactive shell) keeps the first setup
+    let _ = tracing_subscriber::registry().with(stderr).with(file).try_init();
+    Ok(())
+}
+
+// Wraps a MakeWriter so every formatted event is redacted before it is
+// written. tracing-subscriber writes each event with a single call.
+struct Redacting<M>(M);
+
+struct RedactingWriter<W>(W);
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for Redacting<M> {
+    type Writer = RedactingWriter<M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter(self.0.make_writer())
+    }
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(redact(&String::from_utf8_lossy(buf)).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).context(format!("Failed to create {}", parent.display()))?;
+        }
+        let file = config::open_private(path, true).context(format!("Failed to open log file {}", path.display()))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self { path: path.to_path_buf(), file, size })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(".{}", index));
+        self.path.with_file_name(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..KEPT_LOG_FILES).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = config::open_private(&self.path, true)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > MAX_LOG_BYTES {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -1,12 +1,82 @@
-use clap::{Parser, Subcommand};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use commands::App;
+use config::ConfigManager;
+use models::{ConfigFormat, ConflictPolicy, LogLevel, OutputFormat, SettingSource, Settings};
+use std::path::PathBuf;
+use ubus::ClientOptions;
 mod config;
+mod encryption;
+mod firmware;
+mod leds;
+mod logging;
 mod models;
+mod netrc;
+mod packages;
 mod commands;
+mod shell;
+mod ssh;
+mod system;
+mod ubus;
+mod wireless;
+mod zoneinfo;
 
 #[derive(Parser)]
 #[command(name = "wrtcli")]
 #[command(about = "OpenWrt CLI management tool", long_about = None)]
 struct Cli {
+    /// Config file to use instead of the default location
+    #[arg(long, global = true, env = "WRTCLI_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Profile to use, a separate config under profiles/ ("default" for the top-level one)
+    #[arg(long, global = true, env = "WRTCLI_PROFILE")]
+    profile: Option<String>,
+
+    /// Print extra details, such as the config file in use
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Device to use when a command is run without a device name
+    #[arg(long, global = true, env = "WRTCLI_DEVICE")]
+    device: Option<String>,
+
+    /// Seconds to wait for a connection to the device before giving up [default: 3]
+    #[arg(long, global = true, env = "WRTCLI_CONNECT_TIMEOUT")]
+    connect_timeout: Option<u64>,
+
+    /// Number of devices queried at once by --all commands [default: 8]
+    #[arg(long, global = true, env = "WRTCLI_CONCURRENCY", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    concurrency: Option<usize>,
+
+    /// Output format for commands that support JSON [default: text]
+    #[arg(long, global = true, value_enum, env = "WRTCLI_OUTPUT")]
+    output: Option<OutputFormat>,
+
+    /// Directory to save device configuration backups in [default: backups/ in the data directory]
+    #[arg(long, global = true, env = "WRTCLI_BACKUP_DIR")]
+    backup_dir: Option<PathBuf>,
+
+    /// Most verbose log level [default: warn, or info with a log file]
+    #[arg(long, global = true, value_enum, env = "WRTCLI_LOG_LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// Write the log to this file (rotated by size); stderr then only gets warnings and errors
+    #[arg(long, global = true, env = "WRTCLI_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
+    /// Don't look up missing device credentials in ~/.netrc (or $NETRC)
+    #[arg(long, global = true)]
+    no_netrc: bool,
+
+    /// Trust and record the SSH host key of a device seen for the first time, instead of asking
+    #[arg(long, global = true)]
+    accept_new_host_key: bool,
+
+    /// Extra HTTP header sent to devices, as key=value (repeatable)
+    #[arg(long = "header", global = true, value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -20,49 +90,945 @@ enum Commands {
         /// IP address of the device
         #[arg(long)]
         ip: String,
-        /// Username for authentication
+        /// Username for authentication [default: the login from ~/.netrc]
         #[arg(long)]
-        user: String,
-        /// Password for authentication
+        user: Option<String>,
+        /// Password for authentication. Visible in shell history and `ps`, so
+        /// prefer leaving it out: wrtcli then uses ~/.netrc if it has an entry
+        /// for the device, and otherwise prompts for it without echo, or reads
+        /// one line from stdin when stdin is not a terminal
         #[arg(long)]
-        password: String,
+        password: Option<String>,
+        /// Command that prints the password (e.g. a secrets manager CLI)
+        #[arg(long, conflicts_with = "password")]
+        password_command: Option<String>,
+        /// HTTP header saved with the device and sent with every request to it, as key=value (repeatable)
+        #[arg(long = "device-header", value_parser = parse_header)]
+        device_headers: Vec<(String, String)>,
     },
     /// List all registered devices
-    List,
+    List {
+        /// Field to sort the device list by
+        #[arg(long, value_enum, default_value_t = commands::DeviceSort::Name)]
+        sort: commands::DeviceSort,
+    },
     /// Get status of an OpenWrt device
     Status {
-        /// Name of the device
-        name: String,
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Query every registered device concurrently
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+        /// With --all, abort on the first device that fails
+        #[arg(long, requires = "all")]
+        fail_fast: bool,
         /// Display raw values (KB, seconds) instead of human readable format
         #[arg(long)]
         raw: bool,
-        /// Output in JSON format
-        #[arg(long)]
+        /// Output in JSON format (shorthand for --format json)
+        #[arg(long, conflicts_with = "format")]
         json: bool,
+        /// Output format [default: text, or json with --output json]
+        #[arg(long, value_enum)]
+        format: Option<commands::StatusFormat>,
+        /// Append the result to the device's status history
+        #[arg(long)]
+        record: bool,
+    },
+    /// Summarize status samples recorded with `status --record`
+    History {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Only include samples after this date, timestamp or duration ago (e.g. 2024-01-01, 24h)
+        #[arg(long)]
+        since: Option<String>,
     },
     /// Reboot an OpenWrt device
     Reboot {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Wait until the device has rebooted and is reachable again
+        #[arg(long)]
+        wait: bool,
+        /// Seconds to wait for the device to come back
+        #[arg(long, default_value_t = 300, requires = "wait")]
+        timeout: u64,
+    },
+    /// Change a device's hostname
+    #[command(allow_missing_positional = true)]
+    SetHostname {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// New hostname
+        hostname: String,
+    },
+    /// Bring a network interface up (like `ifup`) and wait for it
+    #[command(allow_missing_positional = true)]
+    Ifup {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Logical interface (e.g. "wan")
+        interface: String,
+        /// Seconds to wait for the interface to come up
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+        /// Output the result and the interface's final status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Take a network interface down (like `ifdown`) and wait for it
+    #[command(allow_missing_positional = true)]
+    Ifdown {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Logical interface (e.g. "wan")
+        interface: String,
+        /// Seconds to wait for the interface to go down
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+        /// Take down the interface wrtcli reaches the device through, locking yourself out
+        #[arg(long = "yes-i-know")]
+        yes_i_know: bool,
+        /// Output the result and the interface's final status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Monitor live RX/TX throughput of a network interface
+    Monitor {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Interface to monitor (logical name like "lan" or device like "eth0")
+        #[arg(default_value = "wan")]
+        iface: String,
+        /// Polling interval in seconds
+        #[arg(long, default_value_t = 1)]
+        interval: u64,
+        /// Output one JSON sample per interval
+        #[arg(long)]
+        json: bool,
+    },
+    /// Copy a local file to the device over SCP
+    #[command(override_usage = "wrtcli push [NAME] <LOCAL> <REMOTE>")]
+    Push {
+        /// Name of the device (optional, defaults to --device or the configured default),
+        /// the local file to upload and its destination path on the device
+        #[arg(required = true, num_args = 2..=3, value_names = ["NAME", "LOCAL", "REMOTE"])]
+        args: Vec<String>,
+    },
+    /// Copy a file from the device over SCP
+    #[command(override_usage = "wrtcli pull [NAME] <REMOTE> <LOCAL>")]
+    Pull {
+        /// Name of the device (optional, defaults to --device or the configured default),
+        /// the path of the file on the device and the local destination file or directory
+        #[arg(required = true, num_args = 2..=3, value_names = ["NAME", "REMOTE", "LOCAL"])]
+        args: Vec<String>,
+    },
+    /// Show the RPC user, session timeout and ACLs granted on the device
+    Whoami {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Diagnose the config and a device's ubus, rpcd and LuCI setup
+    Doctor {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Flash a firmware image with sysupgrade, after verifying it and backing up the device
+    Sysupgrade {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// The sysupgrade image to flash
+        #[arg(long)]
+        image: PathBuf,
+        /// Expected sha256 checksum of the image
+        #[arg(long, conflicts_with = "sums")]
+        sha256: Option<String>,
+        /// A sha256sums file listing the image's checksum
+        #[arg(long)]
+        sums: Option<PathBuf>,
+        /// Keep the device's settings (the default)
+        #[arg(long, conflicts_with = "discard_config")]
+        keep_config: bool,
+        /// Erase the device's settings, like `sysupgrade -n`
+        #[arg(short = 'n', long, conflicts_with = "wait")]
+        discard_config: bool,
+        /// Don't download a configuration backup first
+        #[arg(long)]
+        no_backup: bool,
+        /// Wait for the device to come back and report its new firmware version
+        #[arg(long)]
+        wait: bool,
+        /// Maximum number of seconds to wait with --wait
+        #[arg(long, default_value_t = 600)]
+        timeout: u64,
+        /// Don't ask before each step
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Check devices for newer OpenWrt releases
+    Firmware {
+        #[command(subcommand)]
+        command: FirmwareCommands,
+    },
+    /// Manage the packages installed on a device (opkg, or apk on recent snapshots)
+    Opkg {
+        #[command(subcommand)]
+        command: OpkgCommands,
+    },
+    /// Manage the device's network stack
+    Network {
+        #[command(subcommand)]
+        command: NetworkCommands,
+    },
+    /// Change the device's system settings
+    System {
+        #[command(subcommand)]
+        command: SystemCommands,
+    },
+    /// Show and set the device's LEDs
+    Led {
+        #[command(subcommand)]
+        command: LedCommands,
+    },
+    /// Control a device's radios
+    Wifi {
+        #[command(subcommand)]
+        command: WifiCommands,
+    },
+    /// Advanced: low-level access to the device's ubus objects
+    Ubus {
+        #[command(subcommand)]
+        command: UbusCommands,
+    },
+    /// Start an interactive shell for running several commands against a device
+    Shell,
+    /// Manage wrtcli configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Manage profiles, separate configs for separate fleets
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List profiles, marking the one in use
+    List {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Create an empty profile
+    Create {
+        /// Name of the profile
+        name: String,
+    },
+    /// Delete a profile and its history
+    Remove {
+        /// Name of the profile
+        name: String,
+        /// Remove the profile even if it still has devices
+        #[arg(long)]
+        force: bool,
+    },
+    /// Use a profile by default ("default" for the top-level config)
+    Use {
+        /// Name of the profile
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum UbusCommands {
+    /// List ubus objects and their methods
+    List {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Only list objects matching this pattern (e.g. "network.*")
+        #[arg(long, default_value = "*")]
+        pattern: String,
+    },
+    /// Call a ubus method and print the JSON result
+    #[command(override_usage = "wrtcli ubus call [NAME] <OBJECT> <METHOD> [PARAMS]")]
+    Call {
+        /// Name of the device (optional, defaults to --device or the configured default),
+        /// the ubus object (e.g. "system"), the method to call (e.g. "board") and optionally
+        /// the method arguments as a JSON object
+        #[arg(required = true, num_args = 2..=4, value_names = ["NAME", "OBJECT", "METHOD", "PARAMS"])]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum OpkgCommands {
+    /// List installed packages with the version available from the package index
+    List {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Only list packages with a newer version available
+        #[arg(long)]
+        upgradable_only: bool,
+        /// Only list packages whose name contains this text
+        #[arg(long)]
+        filter: Option<String>,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Refresh the package lists, then install packages over SSH
+    #[command(allow_missing_positional = true)]
+    Install {
+        /// Name of the device (defaults to --device or the configured default; with several
+        /// packages, put `--` before them to use the default device)
+        name: Option<String>,
+        /// Packages to install
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Skip refreshing the package lists first
+        #[arg(long)]
+        no_update: bool,
+        /// Seconds to allow for refreshing the package lists
+        #[arg(long, default_value_t = 120)]
+        update_timeout: u64,
+    },
+    /// Report pending package updates on every device, or upgrade them one package at a time
+    Upgrade {
+        /// Refresh the package lists and report the upgradable packages
+        #[arg(long, required_unless_present = "apply")]
+        check: bool,
+        /// Upgrade each upgradable package separately (never a blanket upgrade)
+        #[arg(long, conflicts_with = "check")]
+        apply: bool,
+        /// With --apply, also upgrade the kernel, kernel modules, base-files and libc
+        #[arg(long, requires = "apply")]
+        include_risky: bool,
+        /// Seconds to allow for refreshing the package lists
+        #[arg(long, default_value_t = 120)]
+        update_timeout: u64,
+        /// Abort on the first device that fails
+        #[arg(long)]
+        fail_fast: bool,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Remove packages over SSH
+    #[command(allow_missing_positional = true)]
+    Remove {
+        /// Name of the device (defaults to --device or the configured default; with several
+        /// packages, put `--` before them to use the default device)
+        name: Option<String>,
+        /// Packages to remove
+        #[arg(required = true)]
+        packages: Vec<String>,
+        /// Also remove packages remote management depends on (dropbear, uhttpd, rpcd, luci)
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum NetworkCommands {
+    /// Restart the network (`/etc/init.d/network restart`) and wait for the device to come back,
+    /// after saving its network config locally
+    Restart {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Seconds to wait for the device to be reachable again
+        #[arg(long, default_value_t = 120)]
+        timeout: u64,
+        /// Restart without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SystemCommands {
+    /// Set the hostname and/or time zone in /etc/config/system and apply them
+    Set {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// New hostname
+        #[arg(long, required_unless_present = "timezone")]
+        hostname: Option<String>,
+        /// Time zone name, e.g. Europe/Vienna or UTC; sets both `zonename` and the matching POSIX `timezone`
+        #[arg(long, visible_alias = "zonename")]
+        timezone: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LedCommands {
+    /// List the LEDs with their trigger and brightness
+    List {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Change an LED's trigger and/or brightness
+    #[command(allow_missing_positional = true)]
+    Set {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// LED name as listed by `led list` (e.g. green:power)
+        led: String,
+        /// Trigger to use, e.g. none, default-on or heartbeat (see /sys/class/leds/<led>/trigger)
+        #[arg(long, required_unless_present = "brightness")]
+        trigger: Option<String>,
+        /// Brightness from 0 to the LED's maximum
+        #[arg(long)]
+        brightness: Option<u32>,
+        /// Also save the setting in /etc/config/system so it survives a reboot
+        #[arg(long)]
+        persist: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum WifiCommands {
+    /// Turn radios on
+    Enable {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Only this radio (e.g. radio0) instead of all of them
+        #[arg(long)]
+        radio: Option<String>,
+    },
+    /// Turn radios off
+    Disable {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Only this radio (e.g. radio0) instead of all of them
+        #[arg(long)]
+        radio: Option<String>,
+        /// Turn the radios back on after this long (e.g. 30m, 2h), from a cron entry on the device
+        #[arg(long)]
+        timer: Option<String>,
+    },
+    /// Change the SSID and/or passphrase of a wifi network, and wait until it runs with them
+    Set {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Update every registered device that has the network
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+        /// With --all, abort on the first device that fails
+        #[arg(long, requires = "all")]
+        fail_fast: bool,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+        /// The network to change: its SSID or UCI section name (e.g. default_radio0)
+        #[arg(long)]
+        ssid: String,
+        /// SSID to rename the network to
+        #[arg(long, required_unless_present = "new_password")]
+        new_ssid: Option<String>,
+        /// New passphrase. Give the flag without a value to be prompted for it
+        /// without echo (or to read one line from stdin), which keeps it out
+        /// of shell history and `ps`
+        #[arg(long)]
+        new_password: Option<Option<String>>,
+    },
+    /// Restart the wireless stack (`wifi down` and `wifi up`) and wait for the radios to come back
+    Restart {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Only this radio (e.g. radio0) instead of all of them
+        #[arg(long)]
+        radio: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum FirmwareCommands {
+    /// Report whether a newer stable release exists for the device's target
+    Check {
+        /// Name of the device (defaults to --device or the configured default)
+        name: Option<String>,
+        /// Check every registered device concurrently
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+        /// With --all, abort on the first device that fails
+        #[arg(long, requires = "all")]
+        fail_fast: bool,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+        /// Base URL of the OpenWrt downloads server (or a mirror)
+        #[arg(long, env = "WRTCLI_FIRMWARE_FEED", default_value = firmware::DEFAULT_FEED_URL)]
+        feed_url: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Set the device used when no device name is given
+    SetDefault {
         /// Name of the device
         name: String,
     },
+    /// Save an HTTP header with a device, sent with every request to it
+    SetHeader {
+        /// Name of the device
+        name: String,
+        /// Header as key=value
+        #[arg(value_parser = parse_header)]
+        header: (String, String),
+    },
+    /// Remove an HTTP header saved with a device
+    UnsetHeader {
+        /// Name of the device
+        name: String,
+        /// Header name
+        key: String,
+    },
+    /// Check the config file for mistakes such as mismatched names or bad addresses
+    Validate {
+        /// Also fail when there are only warnings
+        #[arg(long)]
+        strict: bool,
+        /// Output the findings in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Encrypt the config file with a passphrase (read from WRTCLI_PASSPHRASE or prompted)
+    Encrypt,
+    /// Convert an encrypted config file back to plain TOML
+    Decrypt,
+    /// Change a setting in the config file's [settings] table
+    Set {
+        /// Setting name (run 'wrtcli config get' to list them)
+        key: String,
+        /// New value
+        value: String,
+    },
+    /// Show a setting, or all settings when no name is given
+    Get {
+        /// Setting name
+        key: Option<String>,
+    },
+    /// Remove a setting so the built-in default applies
+    Unset {
+        /// Setting name
+        key: String,
+    },
+    /// Print the effective configuration with secrets redacted
+    Show {
+        /// Output in JSON format instead of TOML
+        #[arg(long)]
+        json: bool,
+        /// Print passwords and header values as they are
+        #[arg(long)]
+        unredacted: bool,
+    },
+    /// Import the devices of another config file (TOML or JSON)
+    Import {
+        /// Config file to import from
+        file: PathBuf,
+        /// What to do with devices whose name is already taken
+        #[arg(long, value_enum, default_value = "skip")]
+        on_conflict: ConflictPolicy,
+        /// Report what would be imported without changing the config
+        #[arg(long)]
+        dry_run: bool,
+        /// Output the report in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Open the config in $VISUAL/$EDITOR and save it once it validates
+    Edit {
+        /// Only edit the [devices] table
+        #[arg(long)]
+        devices_only: bool,
+    },
+    /// Make the config and history files readable by the current user only
+    FixPerms,
+    /// Move an existing ~/.wrtcli directory to the platform's standard locations,
+    /// or with --to, convert the config file to another format in place
+    Migrate {
+        /// Convert the config file to this format instead
+        #[arg(long, value_enum)]
+        to: Option<ConfigFormat>,
+    },
+    /// Show where wrtcli stores its config, history and cache files
+    Path {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    let config = open_config(cli.config.clone(), cli.profile.clone(), cli.verbose)?;
+    netrc::set_enabled(!cli.no_netrc);
+    if let Some(config) = &config {
+        ssh::set_known_hosts(config.known_hosts_path(), cli.accept_new_host_key);
+    }
+    let options = client_options(&cli, &matches, config.as_ref());
+    logging::init(options.log_level, options.log_file.as_deref())?;
+    warn_insecure_permissions(&cli, config.as_ref());
 
     match cli.command {
-        Commands::Add { name, ip, user, password } => {
-            commands::add_device(&name, &ip, &user, &password).await?;
+        Commands::Shell => shell::run_shell(cli.device).await,
+        command => {
+            let app = App::new(config, options);
+            let result = dispatch(&app, command, cli.device.as_deref()).await;
+            // A failed command on the device passes its exit status on
+            if let Some(exit) = result.as_ref().err().and_then(|err| err.downcast_ref::<ssh::RemoteExit>()) {
+                eprintln!("Error: {}", exit);
+                std::process::exit(exit.code);
+            }
+            result
+        }
+    }
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    let (key, value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got '{}'", value))?;
+    Ok((key.trim().to_string(), value.trim().to_string()))
+}
+
+// Take the device name off the front of a command's positionals when it
+// was given
+fn split_device_name(mut args: Vec<String>, named: bool) -> (Option<String>, Vec<String>) {
+    let name = named.then(|| args.remove(0));
+    (name, args)
+}
+
+// Point every ConfigManager in this process at the chosen config file or
+// profile, and open it for the command. A config that can't be opened is
+// reported by the command that needs it (or right away with --verbose).
+fn open_config(path: Option<PathBuf>, profile: Option<String>, verbose: bool) -> anyhow::Result<Option<ConfigManager>> {
+    config::set_config_override(path);
+    config::set_profile_override(profile);
+    if verbose {
+        let config = ConfigManager::new()?;
+        eprintln!("📄 Using config file: {}", config.config_path().display());
+        return Ok(Some(config));
+    }
+    Ok(ConfigManager::new().ok())
+}
+
+// Config files hold device passwords, so point out when other users can
+// read them
+fn warn_insecure_permissions(cli: &Cli, config: Option<&ConfigManager>) {
+    if matches!(cli.command, Commands::Config { command: ConfigCommands::FixPerms }) {
+        return;
+    }
+    let Some(config) = config else {
+        return;
+    };
+    for path in config.insecure_paths() {
+        eprintln!("⚠️  {} is accessible by other users; run 'wrtcli config fix-perms'", path.display());
+    }
+}
+
+// Pick the effective value of a setting and record where it came from: the
+// flag or its environment variable, the config file's [settings], or the
+// built-in default
+fn resolve_setting<T>(
+    matches: &ArgMatches,
+    key: &'static str,
+    arg_id: &str,
+    arg: Option<T>,
+    file: Option<T>,
+    default: T,
+    sources: &mut Vec<(&'static str, SettingSource)>,
+) -> T {
+    let (value, source) = match (arg, file) {
+        (Some(value), _) if matches.value_source(arg_id) == Some(ValueSource::EnvVariable) => (value, SettingSource::Env),
+        (Some(value), _) => (value, SettingSource::Flag),
+        (None, Some(value)) => (value, SettingSource::File),
+        (None, None) => (default, SettingSource::Default),
+    };
+    sources.push((key, source));
+    value
+}
+
+// Resolve the global options: flag or environment variable first, then the
+// config file's [settings], then the built-in default
+fn client_options(cli: &Cli, matches: &ArgMatches, config: Option<&ConfigManager>) -> ClientOptions {
+    // A config that can't be loaded is reported by the command that needs it
+    let settings = config
+        .and_then(|config| config.load_config().ok())
+        .map(|config| config.settings)
+        .unwrap_or_default();
+
+    let mut sources = Vec::new();
+    ClientOptions {
+        connect_timeout: resolve_setting(
+            matches,
+            "connect_timeout",
+            "connect_timeout",
+            cli.connect_timeout,
+            settings.connect_timeout,
+            Settings::DEFAULT_CONNECT_TIMEOUT,
+            &mut sources,
+        ),
+        headers: cli.headers.clone(),
+        concurrency: resolve_setting(
+            matches,
+            "concurrency",
+            "concurrency",
+            cli.concurrency,
+            settings.concurrency,
+            Settings::DEFAULT_CONCURRENCY,
+            &mut sources,
+        ),
+        output: resolve_setting(matches, "output", "output", cli.output, settings.output, OutputFormat::Text, &mut sources),
+        backup_dir: resolve_setting(
+            matches,
+            "backup_dir",
+            "backup_dir",
+            cli.backup_dir.clone().map(Some),
+            settings.backup_dir.map(Some),
+            None,
+            &mut sources,
+        ),
+        log_level: resolve_setting(
+            matches,
+            "logging.level",
+            "log_level",
+            cli.log_level.map(Some),
+            settings.logging.level.map(Some),
+            None,
+            &mut sources,
+        ),
+        log_file: resolve_setting(
+            matches,
+            "logging.file",
+            "log_file",
+            cli.log_file.clone().map(Some),
+            settings.logging.file.map(Some),
+            None,
+            &mut sources,
+        ),
+        sources,
+    }
+}
+
+// Run a single (non-shell) command
+async fn dispatch(app: &App, command: Commands, device: Option<&str>) -> anyhow::Result<()> {
+    let options = app.options();
+    match command {
+        Commands::Add { name, ip, user, password, password_command, device_headers } => {
+            app.add_device(&name, &ip, user.as_deref(), password.as_deref(), password_command.as_deref(), device_headers)
+                .await?;
+        }
+        Commands::List { sort } => {
+            app.list_devices(sort).await?;
+        }
+        Commands::Status { name, all, fail_fast, raw, json, format, record } => {
+            let format = match format {
+                _ if json => commands::StatusFormat::Json,
+                Some(format) => format,
+                None if options.json(false) => commands::StatusFormat::Json,
+                None => commands::StatusFormat::Text,
+            };
+            if all {
+                app.get_status_all(raw, format, fail_fast, record).await?;
+            } else {
+                app.get_status(name.as_deref().or(device), raw, format, record).await?;
+            }
+        }
+        Commands::History { name, since } => {
+            app.show_history(name.as_deref().or(device), since.as_deref()).await?;
+        }
+        Commands::Reboot { name, wait, timeout } => {
+            app.reboot_device(name.as_deref().or(device), wait, timeout).await?;
+        }
+        Commands::SetHostname { name, hostname } => {
+            app.set_system(name.as_deref().or(device), Some(&hostname), None).await?;
+        }
+        Commands::Ifup { name, interface, timeout, json } => {
+            app.set_interface(name.as_deref().or(device), &interface, true, timeout, false, options.json(json)).await?;
+        }
+        Commands::Ifdown { name, interface, timeout, yes_i_know, json } => {
+            app.set_interface(name.as_deref().or(device), &interface, false, timeout, yes_i_know, options.json(json)).await?;
+        }
+        Commands::Monitor { name, iface, interval, json } => {
+            app.monitor_traffic(name.as_deref().or(device), &iface, interval, options.json(json)).await?;
+        }
+        Commands::Push { args } => {
+            let named = args.len() == 3;
+            let (name, args) = split_device_name(args, named);
+            app.push_file(name.as_deref().or(device), &args[0], &args[1]).await?;
+        }
+        Commands::Pull { args } => {
+            let named = args.len() == 3;
+            let (name, args) = split_device_name(args, named);
+            app.pull_file(name.as_deref().or(device), &args[0], &args[1]).await?;
+        }
+        Commands::Whoami { name, json } => {
+            app.whoami(name.as_deref().or(device), options.json(json)).await?;
         }
-        Commands::List => {
-            commands::list_devices().await?;
+        Commands::Doctor { name, json } => {
+            app.doctor(name.as_deref().or(device), options.json(json)).await?;
         }
-        Commands::Status { name, raw, json } => {
-            commands::get_status(&name, raw, json).await?;
+        Commands::Sysupgrade { name, image, sha256, sums, keep_config: _, discard_config, no_backup, wait, timeout, yes } => {
+            let upgrade = commands::SysupgradeOptions {
+                image,
+                sha256,
+                sums,
+                keep_config: !discard_config,
+                backup: !no_backup,
+                wait,
+                timeout,
+                yes,
+            };
+            app.sysupgrade(name.as_deref().or(device), upgrade).await?;
         }
-        Commands::Reboot { name } => {
-            commands::reboot_device(&name).await?;
+        Commands::Firmware { command } => match command {
+            FirmwareCommands::Check { name, all, fail_fast, json, feed_url } => {
+                if all {
+                    app.check_firmware_all(&feed_url, options.json(json), fail_fast).await?;
+                } else {
+                    app.check_firmware(name.as_deref().or(device), &feed_url, options.json(json)).await?;
+                }
+            }
+        },
+        Commands::Opkg { command } => match command {
+            OpkgCommands::List { name, upgradable_only, filter, json } => {
+                app.list_packages(name.as_deref().or(device), upgradable_only, filter.as_deref(), options.json(json)).await?;
+            }
+            OpkgCommands::Install { name, packages, no_update, update_timeout } => {
+                app.install_packages(name.as_deref().or(device), &packages, !no_update, update_timeout).await?;
+            }
+            OpkgCommands::Upgrade { check: _, apply, include_risky, update_timeout, fail_fast, json } => {
+                app.upgrade_packages(apply, include_risky, update_timeout, fail_fast, options.json(json)).await?;
+            }
+            OpkgCommands::Remove { name, packages, force } => {
+                app.remove_packages(name.as_deref().or(device), &packages, force).await?;
+            }
+        },
+        Commands::Network { command } => match command {
+            NetworkCommands::Restart { name, timeout, yes } => {
+                app.restart_network(name.as_deref().or(device), timeout, yes).await?;
+            }
+        },
+        Commands::System { command } => match command {
+            SystemCommands::Set { name, hostname, timezone } => {
+                app.set_system(name.as_deref().or(device), hostname.as_deref(), timezone.as_deref()).await?;
+            }
+        },
+        Commands::Led { command } => match command {
+            LedCommands::List { name, json } => {
+                app.list_leds(name.as_deref().or(device), options.json(json)).await?;
+            }
+            LedCommands::Set { name, led, trigger, brightness, persist } => {
+                app.set_led(name.as_deref().or(device), &led, trigger.as_deref(), brightness, persist).await?;
+            }
+        },
+        Commands::Wifi { command } => match command {
+            WifiCommands::Enable { name, radio } => {
+                app.set_wifi(name.as_deref().or(device), radio.as_deref(), true, None).await?;
+            }
+            WifiCommands::Disable { name, radio, timer } => {
+                app.set_wifi(name.as_deref().or(device), radio.as_deref(), false, timer.as_deref()).await?;
+            }
+            WifiCommands::Set { name, all, fail_fast, json, ssid, new_ssid, new_password } => {
+                let update = commands::WifiUpdate { target: ssid, new_ssid, new_password };
+                app.set_wifi_network(name.as_deref().or(device), all, fail_fast, options.json(json), update).await?;
+            }
+            WifiCommands::Restart { name, radio } => {
+                app.restart_wifi(name.as_deref().or(device), radio.as_deref()).await?;
+            }
+        },
+        Commands::Ubus { command } => match command {
+            UbusCommands::List { name, pattern } => {
+                app.ubus_list(name.as_deref().or(device), &pattern).await?;
+            }
+            UbusCommands::Call { args } => {
+                // Params are a JSON object, which tells `NAME OBJECT METHOD`
+                // apart from `OBJECT METHOD PARAMS`
+                let named = args.len() == 4 || (args.len() == 3 && !args[2].trim_start().starts_with('{'));
+                let (name, args) = split_device_name(args, named);
+                let params = args.get(2).map(String::as_str);
+                app.ubus_call(name.as_deref().or(device), &args[0], &args[1], params).await?;
+            }
+        },
+        Commands::Config { command } => match command {
+            ConfigCommands::SetDefault { name } => {
+                app.set_default_device(&name).await?;
+            }
+            ConfigCommands::SetHeader { name, header: (key, value) } => {
+                app.set_device_header(&name, &key, &value).await?;
+            }
+            ConfigCommands::UnsetHeader { name, key } => {
+                app.unset_device_header(&name, &key).await?;
+            }
+            ConfigCommands::Validate { strict, json } => {
+                app.validate_config(strict, options.json(json)).await?;
+            }
+            ConfigCommands::Encrypt => {
+                app.encrypt_config().await?;
+            }
+            ConfigCommands::Decrypt => {
+                app.decrypt_config().await?;
+            }
+            ConfigCommands::Set { key, value } => {
+                app.set_setting(&key, &value).await?;
+            }
+            ConfigCommands::Get { key } => {
+                app.get_setting(key.as_deref()).await?;
+            }
+            ConfigCommands::Unset { key } => {
+                app.unset_setting(&key).await?;
+            }
+            ConfigCommands::Show { json, unredacted } => {
+                app.show_config(options.json(json), unredacted).await?;
+            }
+            ConfigCommands::Import { file, on_conflict, dry_run, json } => {
+                app.import_config(&file, on_conflict, dry_run, options.json(json)).await?;
+            }
+            ConfigCommands::Edit { devices_only } => {
+                app.edit_config(devices_only).await?;
+            }
+            ConfigCommands::FixPerms => {
+                app.fix_permissions().await?;
+            }
+            ConfigCommands::Migrate { to } => {
+                app.migrate_config(to).await?;
+            }
+            ConfigCommands::Path { json } => {
+                app.show_config_paths(options.json(json)).await?;
+            }
+        },
+        Commands::Profile { command } => match command {
+            ProfileCommands::List { json } => {
+                app.list_profiles(options.json(json)).await?;
+            }
+            ProfileCommands::Create { name } => {
+                app.create_profile(&name).await?;
+            }
+            ProfileCommands::Remove { name, force } => {
+                app.remove_profile(&name, force).await?;
+            }
+            ProfileCommands::Use { name } => {
+                app.use_profile(&name).await?;
+            }
+        },
+        Commands::Shell => {
+            anyhow::bail!("Already in the interactive shell");
         }
     }
 
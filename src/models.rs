@@ -1,40 +1,214 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// A password or session token. Debug and Display print `***` so it can't
+/// leak through logging; it is serialized (e.g. to the config) as-is.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// The actual value, for sending it to the device
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Device {
     pub name: String,
     pub ip: String,
     pub user: String,
-    pub password: String,
+    pub password: Secret,
+    /// Command whose stdout is used as the password instead of `password`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
+    /// Extra HTTP headers sent with every request to the device
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Debug output redacts the secrets the same way `Secret` does: header values
+/// (which typically carry tokens) and the password command (which may name one)
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut headers: Vec<(&str, &str)> = self.headers.keys().map(|key| (key.as_str(), "***")).collect();
+        headers.sort();
+        f.debug_struct("Device")
+            .field("name", &self.name)
+            .field("ip", &self.ip)
+            .field("user", &self.user)
+            .field("password", &self.password)
+            .field("password_command", &self.password_command.as_ref().map(|_| "***"))
+            .field("headers", &headers)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Config {
+    /// Device used when a command is run without a device name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_device: Option<String>,
     pub devices: HashMap<String, Device>,
+    /// Tool-wide preferences, managed with `wrtcli config set/get`
+    #[serde(default, skip_serializing_if = "Settings::is_empty")]
+    pub settings: Settings,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SystemStatus {
-    pub hostname: String,
-    pub model: String,
-    pub uptime: u64,
-    pub load: Vec<f64>,
-    pub memory: MemoryStatus,
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// What `config import` does with a device whose name is already taken
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Keep the existing device
+    Skip,
+    /// Replace the existing device with the imported one
+    Overwrite,
+    /// Import the device under a new name with a numeric suffix
+    Rename,
+}
+
+/// Format of the config file, chosen by its extension
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ConfigFormat {
+    Toml,
+    Json,
 }
 
+impl ConfigFormat {
+    pub fn of(path: &std::path::Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("json") => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Toml => "toml",
+            Self::Json => "json",
+        }
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String> {
+        Ok(match self {
+            Self::Toml => toml::to_string_pretty(value)?,
+            Self::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+
+    pub fn parse<T: serde::de::DeserializeOwned>(self, content: &str) -> Result<T> {
+        Ok(match self {
+            Self::Toml => toml::from_str(content)?,
+            Self::Json => serde_json::from_str(content)?,
+        })
+    }
+}
+
+/// The `[settings]` table. Unset fields fall back to the built-in defaults;
+/// command line flags and environment variables take precedence over them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    /// Seconds to wait for a connection to a device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<u64>,
+    /// Output format for commands that support JSON
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFormat>,
+    /// Devices queried at once by `--all` commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Directory device configuration backups are saved in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_dir: Option<std::path::PathBuf>,
+    /// The `[settings.logging]` table
+    #[serde(default, skip_serializing_if = "LoggingSettings::is_empty")]
+    pub logging: LoggingSettings,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoggingSettings {
+    /// Most verbose level written to the log
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<LogLevel>,
+    /// File the log is written to; stderr then only gets warnings and errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<std::path::PathBuf>,
+}
+
+impl LoggingSettings {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Where the effective value of a setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SettingSource {
+    Flag,
+    Env,
+    File,
+    Default,
+}
+
+/// One `status --record` sample appended to a device's history file
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct MemoryStatus {
-    pub total: u64,
-    pub free: u64,
-    pub buffered: u64,
-    pub cached: u64,
+pub struct StatusRecord {
+    pub timestamp: DateTime<Utc>,
+    pub uptime: u64,
+    pub load: f64,
+    pub memory_total_kb: u64,
+    pub memory_free_kb: u64,
 }
 
 impl Config {
     pub fn new() -> Self {
         Self {
+            default_device: None,
             devices: HashMap::new(),
+            settings: Settings::default(),
         }
     }
 
@@ -47,17 +221,154 @@ impl Config {
     }
 }
 
+impl Settings {
+    pub const DEFAULT_CONNECT_TIMEOUT: u64 = 3;
+    pub const DEFAULT_CONCURRENCY: usize = 8;
+
+    /// Keys accepted by `config set/get`, with a short description
+    pub const KEYS: &'static [(&'static str, &'static str)] = &[
+        ("connect_timeout", "seconds to wait for a connection (default: 3)"),
+        ("output", "text or json (default: text)"),
+        ("concurrency", "devices queried at once by --all commands (default: 8)"),
+        ("backup_dir", "directory device backups are saved in (default: backups/ in the data directory)"),
+        ("logging.level", "off, error, warn, info, debug or trace (default: warn, or info with a log file)"),
+        ("logging.file", "file to write the log to, rotated by size (default: none)"),
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn unknown_key(key: &str) -> anyhow::Error {
+        let keys: Vec<&str> = Self::KEYS.iter().map(|(key, _)| *key).collect();
+        anyhow::anyhow!("Unknown setting '{}'. Valid settings: {}", key, keys.join(", "))
+    }
+
+    /// The configured value of `key`, or `None` when it is unset
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(match key {
+            "connect_timeout" => self.connect_timeout.map(|v| v.to_string()),
+            "output" => self.output.and_then(|v| v.to_possible_value()).map(|v| v.get_name().to_string()),
+            "concurrency" => self.concurrency.map(|v| v.to_string()),
+            "backup_dir" => self.backup_dir.as_ref().map(|v| v.display().to_string()),
+            "logging.level" => self.logging.level.and_then(|v| v.to_possible_value()).map(|v| v.get_name().to_string()),
+            "logging.file" => self.logging.file.as_ref().map(|v| v.display().to_string()),
+            _ => return Err(Self::unknown_key(key)),
+        })
+    }
+
+    pub fn unset(&mut self, key: &str) -> Result<()> {
+        match key {
+            "connect_timeout" => self.connect_timeout = None,
+            "output" => self.output = None,
+            "concurrency" => self.concurrency = None,
+            "backup_dir" => self.backup_dir = None,
+            "logging.level" => self.logging.level = None,
+            "logging.file" => self.logging.file = None,
+            _ => return Err(Self::unknown_key(key)),
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let invalid = || format!("Invalid value '{}' for setting '{}'", value, key);
+        match key {
+            "connect_timeout" => self.connect_timeout = Some(value.parse().with_context(invalid)?),
+            "output" => {
+                self.output = Some(OutputFormat::from_str(value, true).map_err(|_| {
+                    anyhow::anyhow!("{}: expected text or json", invalid())
+                })?)
+            }
+            "concurrency" => {
+                let concurrency: usize = value.parse().with_context(invalid)?;
+                if concurrency == 0 {
+                    bail!("{}: must be at least 1", invalid());
+                }
+                self.concurrency = Some(concurrency);
+            }
+            "logging.level" => {
+                self.logging.level = Some(LogLevel::from_str(value, true).map_err(|_| {
+                    anyhow::anyhow!("{}: expected off, error, warn, info, debug or trace", invalid())
+                })?)
+            }
+            // Paths are stored absolute so they don't depend on where wrtcli is run
+            "backup_dir" => self.backup_dir = Some(std::path::absolute(value).with_context(invalid)?),
+            "logging.file" => self.logging.file = Some(std::path::absolute(value).with_context(invalid)?),
+            _ => return Err(Self::unknown_key(key)),
+        }
+        Ok(())
+    }
+}
+
 impl Device {
     pub fn new(name: String, ip: String, user: String, password: String) -> Self {
         Self {
             name,
             ip,
             user,
-            password,
+            password: Secret::new(password),
+            password_command: None,
+            headers: HashMap::new(),
         }
     }
 
     pub fn ubus_url(&self) -> String {
         format!("http://{}/ubus", self.ip)
     }
+
+    /// Host part of `ip`, without any port
+    pub fn host(&self) -> &str {
+        split_host_port(&self.ip).0
+    }
+
+    /// Port of the device's HTTP server (uhttpd)
+    pub fn http_port(&self) -> u16 {
+        split_host_port(&self.ip).1.unwrap_or(80)
+    }
+}
+
+/// Split "192.168.1.1:8080" into host and port; "[fe80::1]:80" gives
+/// "fe80::1". Bare IPv6 addresses are returned whole.
+pub fn split_host_port(address: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = address.strip_prefix('[') {
+        let (host, port) = rest.split_once(']').unwrap_or((rest, ""));
+        return (host, port.strip_prefix(':').and_then(|port| port.parse().ok()));
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (address, None),
+        },
+        _ => (address, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_debug_output_has_no_secrets() {
+        let mut device = Device::new(
+            "router1".to_string(),
+            "192.168.1.1".to_string(),
+            "root".to_string(),
+            "hunter2".to_string(),
+        );
+        device.password_command = Some("pass show routers/token-4711".to_string());
+        device.headers.insert("Authorization".to_string(), "Bearer eyJhbGciOi".to_string());
+        device.headers.insert("X-Proxy-Token".to_string(), "abc123".to_string());
+
+        let debug = format!("{:?}", device);
+        for secret in ["hunter2", "token-4711", "eyJhbGciOi", "abc123"] {
+            assert!(!debug.contains(secret), "{} leaks {}", debug, secret);
+        }
+        // Everything else is still there
+        assert!(debug.contains("router1") && debug.contains("192.168.1.1") && debug.contains("Authorization"));
+        // Also when nested, as in a Config
+        let mut config = Config::new();
+        config.add_device(device);
+        assert!(!format!("{:?}", config).contains("hunter2"));
+        assert!(!format!("{:#?}", config).contains("abc123"));
+    }
 }
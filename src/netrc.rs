@@ -0,0 +1,181 @@
+use crate::models::split_host_port;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+// Cleared by `--no-netrc`
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+// The netrc file is read once per process; malformed entries are reported
+// the first time only
+static ENTRIES: OnceLock<Vec<Entry>> = OnceLock::new();
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    // `None` for the `default` entry, which matches any machine
+    machine: Option<String>,
+    pub login: Option<String>,
+    pub password: Option<String>,
+}
+
+// $NETRC, or ~/.netrc (~/_netrc on Windows)
+fn netrc_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("NETRC") {
+        return Some(PathBuf::from(path));
+    }
+    let name = if cfg!(windows) { "_netrc" } else { ".netrc" };
+    dirs::home_dir().map(|home| home.join(name))
+}
+
+// Parse netrc content. Problems are returned as warnings, and the entries
+// that could be read are still used.
+fn parse(content: &str) -> (Vec<Entry>, Vec<String>) {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut warnings = Vec::new();
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, line)) = lines.next() {
+        let line_no = index + 1;
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token.starts_with('#') {
+                break;
+            }
+            match token {
+                "machine" => match tokens.next() {
+                    Some(machine) => entries.push(Entry { machine: Some(machine.to_string()), ..Entry::default() }),
+                    None => warnings.push(format!("line {}: 'machine' without a host name", line_no)),
+                },
+                "default" => entries.push(Entry::default()),
+                "login" | "password" | "account" => {
+                    let Some(value) = tokens.next() else {
+                        warnings.push(format!("line {}: '{}' without a value", line_no, token));
+                        continue;
+                    };
+                    let Some(entry) = entries.last_mut() else {
+                        warnings.push(format!("line {}: '{}' before any 'machine' entry", line_no, token));
+                        continue;
+                    };
+                    match token {
+                        "login" => entry.login = Some(value.to_string()),
+                        "password" => entry.password = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+                // A macro definition runs until the next blank line
+                "macdef" => {
+                    for (_, line) in lines.by_ref() {
+                        if line.trim().is_empty() {
+                            break;
+                        }
+                    }
+                    break;
+                }
+                _ => warnings.push(format!("line {}: unexpected '{}'", line_no, token)),
+            }
+        }
+    }
+
+    (entries, warnings)
+}
+
+fn entries() -> &'static [Entry] {
+    ENTRIES.get_or_init(|| {
+        let Some(path) = netrc_path() else {
+            return Vec::new();
+        };
+        let Ok(content) = fs::read_to_string(&path) else {
+            return Vec::new();
+        };
+        let (entries, warnings) = parse(&content);
+        for warning in warnings {
+            eprintln!("⚠️  {}: {}", path.display(), warning);
+        }
+        entries
+    })
+}
+
+// The entry for `host` (a device address, optionally with a port), falling
+// back to the `default` entry. Always `None` with `--no-netrc`.
+pub fn lookup(host: &str) -> Option<Entry> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let host = split_host_port(host).0;
+    let entries = entries();
+    entries
+        .iter()
+        .find(|entry| entry.machine.as_deref() == Some(host))
+        .or_else(|| entries.iter().find(|entry| entry.machine.is_none()))
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_entries_over_several_lines() {
+        let (entries, warnings) = parse(
+            "machine 192.168.1.1\n  login root\n  password secret\n\nmachine router2 login admin password hunter2\n",
+        );
+        assert!(warnings.is_empty());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].machine.as_deref(), Some("192.168.1.1"));
+        assert_eq!(entries[0].login.as_deref(), Some("root"));
+        assert_eq!(entries[0].password.as_deref(), Some("secret"));
+        assert_eq!(entries[1].machine.as_deref(), Some("router2"));
+        assert_eq!(entries[1].password.as_deref(), Some("hunter2"));
+    }
+
+    #[test]
+    fn parses_the_default_entry() {
+        let (entries, warnings) = parse("machine a login x password y\ndefault login anonymous password guest\n");
+        assert!(warnings.is_empty());
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].machine, None);
+        assert_eq!(entries[1].login.as_deref(), Some("anonymous"));
+    }
+
+    #[test]
+    fn skips_macro_definitions() {
+        let (entries, warnings) = parse(
+            "machine a login x password y\nmacdef init\ncd /pub\nmachine fake password leaked\n\nmachine b login z\n",
+        );
+        assert!(warnings.is_empty());
+        let machines: Vec<_> = entries.iter().map(|entry| entry.machine.as_deref()).collect();
+        assert_eq!(machines, vec![Some("a"), Some("b")]);
+        assert_eq!(entries[0].password.as_deref(), Some("y"));
+    }
+
+    #[test]
+    fn skips_comments() {
+        let (entries, warnings) = parse("# routers\nmachine a login x # the lab one\n");
+        assert!(warnings.is_empty());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].login.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn warns_about_malformed_lines_and_keeps_the_rest() {
+        let (entries, warnings) = parse("login early\nmachine a login x\npassword\nbogus token\nmachine\n");
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].login.as_deref(), Some("x"));
+        assert_eq!(
+            warnings,
+            vec![
+                "line 1: 'login' before any 'machine' entry",
+                "line 3: 'password' without a value",
+                "line 4: unexpected 'bogus'",
+                "line 4: unexpected 'token'",
+                "line 5: 'machine' without a host name",
+            ]
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use crate::models::Device;
+use crate::{ssh, ubus};
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::json;
+use ssh2::Session;
+use std::collections::HashMap;
+use std::io;
+
+const OPKG: &str = "/bin/opkg";
+const APK: &str = "/usr/bin/apk";
+
+// Packages that remote management (SSH, the web server and ubus over HTTP)
+// depends on; removing them needs --force
+pub const PROTECTED_PACKAGES: &[&str] = &[
+    "dropbear",
+    "uhttpd",
+    "uhttpd-mod-ubus",
+    "rpcd",
+    "rpcd-mod-file",
+    "luci",
+    "luci-base",
+];
+
+// Packages that are only safe to upgrade together with the rest of the
+// firmware: the kernel and its modules, and the base system and C library
+const RISKY_PACKAGES: &[&str] = &["kernel", "base-files", "libc", "musl"];
+const RISKY_PREFIXES: &[&str] = &["kmod-"];
+
+pub fn is_risky(name: &str) -> bool {
+    RISKY_PACKAGES.contains(&name) || RISKY_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+// Recent snapshots replaced opkg with apk; both are driven through `file
+// exec`, which runs a binary by path
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageManager {
+    Opkg,
+    Apk,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Package {
+    pub name: String,
+    pub installed_version: String,
+    // Set when the package index has a newer version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_version: Option<String>,
+}
+
+impl PackageManager {
+    // Use apk when the device has it (the equivalent of `which apk`)
+    pub async fn detect(client: &Client, device: &Device, session: &str) -> PackageManager {
+        let stat = ubus::call(client, device, session, "file", "stat", json!({ "path": APK })).await;
+        match stat {
+            Ok(_) => PackageManager::Apk,
+            Err(_) => PackageManager::Opkg,
+        }
+    }
+
+    // The same check over an SSH session, where the shell can look it up
+    pub fn detect_over_ssh(session: &Session) -> Result<PackageManager> {
+        match ssh::run_command(session, "command -v apk", None, &mut io::sink())? {
+            0 => Ok(PackageManager::Apk),
+            _ => Ok(PackageManager::Opkg),
+        }
+    }
+
+    pub fn binary(self) -> &'static str {
+        match self {
+            PackageManager::Opkg => OPKG,
+            PackageManager::Apk => APK,
+        }
+    }
+
+    fn list_installed_args(self) -> &'static [&'static str] {
+        match self {
+            PackageManager::Opkg => &["list-installed"],
+            PackageManager::Apk => &["list", "--installed"],
+        }
+    }
+
+    fn list_upgradable_args(self) -> &'static [&'static str] {
+        match self {
+            PackageManager::Opkg => &["list-upgradable"],
+            PackageManager::Apk => &["list", "--upgradable"],
+        }
+    }
+
+    // Shell commands that refresh the package index, and install or remove
+    // `packages`
+    pub fn update_command(self) -> String {
+        format!("{} update", self.binary())
+    }
+
+    pub fn install_command(self, packages: &[String]) -> Result<String> {
+        let verb = match self {
+            PackageManager::Opkg => "install",
+            PackageManager::Apk => "add",
+        };
+        Ok(format!("{} {} {}", self.binary(), verb, shell_words(packages)?))
+    }
+
+    pub fn remove_command(self, packages: &[String]) -> Result<String> {
+        let verb = match self {
+            PackageManager::Opkg => "remove",
+            PackageManager::Apk => "del",
+        };
+        Ok(format!("{} {} {}", self.binary(), verb, shell_words(packages)?))
+    }
+
+    pub fn upgrade_command(self, package: &str) -> Result<String> {
+        Ok(format!("{} upgrade {}", self.binary(), shell_words(&[package.to_string()])?))
+    }
+
+    pub fn list_upgradable_command(self) -> String {
+        format!("{} {}", self.binary(), self.list_upgradable_args().join(" "))
+    }
+
+    // Packages with a newer version, from the output of the upgradable listing
+    pub fn parse_upgradable(self, output: &str) -> Vec<Package> {
+        match self {
+            PackageManager::Opkg => parse_opkg_upgradable(output),
+            PackageManager::Apk => parse_apk_upgradable(output),
+        }
+    }
+
+    // Installed packages with the version available from the device's
+    // package index, sorted by name
+    pub async fn list(self, client: &Client, device: &Device, session: &str) -> Result<Vec<Package>> {
+        let installed = run(client, device, session, self.binary(), self.list_installed_args()).await?;
+        let upgradable = run(client, device, session, self.binary(), self.list_upgradable_args()).await?;
+
+        let mut packages = match self {
+            PackageManager::Opkg => parse_opkg_installed(&installed),
+            PackageManager::Apk => parse_apk_installed(&installed),
+        };
+        let upgrades: HashMap<String, String> = self
+            .parse_upgradable(&upgradable)
+            .into_iter()
+            .filter_map(|package| package.available_version.map(|version| (package.name, version)))
+            .collect();
+        for package in &mut packages {
+            package.available_version = upgrades.get(&package.name).cloned();
+        }
+        packages.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(packages)
+    }
+}
+
+fn shell_words(packages: &[String]) -> Result<String> {
+    shlex::try_join(packages.iter().map(String::as_str)).context("Package names must not contain NUL bytes")
+}
+
+// Run a package manager command and return its standard output
+async fn run(client: &Client, device: &Device, session: &str, binary: &str, args: &[&str]) -> Result<String> {
+    let output = ubus::exec(client, device, session, binary, args).await?;
+    if output.code != 0 {
+        bail!(
+            "'{} {}' exited with {}: {}",
+            binary,
+            args.join(" "),
+            output.code,
+            output.stderr.trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+// `opkg list-installed`: "name - version"
+fn parse_opkg_installed(output: &str) -> Vec<Package> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(2, " - ");
+            let name = fields.next()?.trim();
+            let version = fields.next()?.trim();
+            Some(Package { name: name.to_string(), installed_version: version.to_string(), available_version: None })
+        })
+        .collect()
+}
+
+// `opkg list-upgradable`: "name - installed - available"
+fn parse_opkg_upgradable(output: &str) -> Vec<Package> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(" - ").map(str::trim).collect();
+            match fields.as_slice() {
+                [name, installed, available, ..] => Some(Package {
+                    name: name.to_string(),
+                    installed_version: installed.to_string(),
+                    available_version: Some(available.to_string()),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Split an apk "name-version-rN" into name and version. Package names may
+// contain dashes, but versions always end in a "-rN" release suffix.
+fn split_apk_name_version(value: &str) -> Option<(&str, &str)> {
+    let (rest, release) = value.rsplit_once('-')?;
+    if release.starts_with('r') && release[1..].chars().all(|c| c.is_ascii_digit()) {
+        let (name, _) = rest.rsplit_once('-')?;
+        Some((name, &value[name.len() + 1..]))
+    } else {
+        Some((rest, release))
+    }
+}
+
+// `apk list --installed`:
+// "name-version-rN arch {origin} (license) [installed]"
+fn parse_apk_installed(output: &str) -> Vec<Package> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, version) = split_apk_name_version(line.split_whitespace().next()?)?;
+            Some(Package { name: name.to_string(), installed_version: version.to_string(), available_version: None })
+        })
+        .collect()
+}
+
+// `apk list --upgradable`:
+// "name-new-rN arch {origin} (license) [upgradable from: name-old-rN]"
+fn parse_apk_upgradable(output: &str) -> Vec<Package> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (name, available) = split_apk_name_version(line.split_whitespace().next()?)?;
+            let from = line.split_once("[upgradable from: ")?.1.trim_end().trim_end_matches(']');
+            let (_, installed) = split_apk_name_version(from)?;
+            Some(Package {
+                name: name.to_string(),
+                installed_version: installed.to_string(),
+                available_version: Some(available.to_string()),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `opkg list-installed` and `opkg list-upgradable` as printed on OpenWrt 23.05
+    const OPKG_INSTALLED: &str = "\
+base-files - 1567-r24106-10cc5fcd00
+busybox - 1.36.1-1
+ca-bundle - 20230311-1
+dropbear - 2022.82-6
+kmod-nf-conntrack - 5.15.150-1
+libustream-mbedtls20201210 - 2023.02.25~498f6e26-1
+luci-app-firewall - git-23.236.53405-98c6a29
+";
+    const OPKG_UPGRADABLE: &str = "\
+luci-app-firewall - git-23.236.53405-98c6a29 - git-24.086.45142-09d5a38
+ca-bundle - 20230311-1 - 20240203-1
+";
+
+    // `apk list --installed` and `apk list --upgradable` as printed on snapshots
+    const APK_INSTALLED: &str = "\
+busybox-1.36.1-r1 aarch64_cortex-a53 {busybox} (GPL-2.0) [installed]
+kmod-nf-conntrack-6.6.73-r1 aarch64_cortex-a53 {linux} (GPL-2.0) [installed]
+luci-app-firewall-24.086.45142~09d5a38-r1 noarch {luci-app-firewall} (Apache-2.0) [installed]
+libustream-mbedtls-2024.07.28~99bd3d2b-r1 aarch64_cortex-a53 {ustream-ssl} (ISC) [installed]
+";
+    const APK_UPGRADABLE: &str = "\
+luci-app-firewall-25.010.12345~1a2b3c4-r1 noarch {luci-app-firewall} (Apache-2.0) [upgradable from: luci-app-firewall-24.086.45142~09d5a38-r1]
+kmod-nf-conntrack-6.6.74-r1 aarch64_cortex-a53 {linux} (GPL-2.0) [upgradable from: kmod-nf-conntrack-6.6.73-r1]
+";
+
+    fn names_and_versions(packages: &[Package]) -> Vec<(&str, &str, Option<&str>)> {
+        packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.installed_version.as_str(), p.available_version.as_deref()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_opkg_installed() {
+        let packages = parse_opkg_installed(OPKG_INSTALLED);
+        assert_eq!(packages.len(), 7);
+        assert_eq!(names_and_versions(&packages)[0], ("base-files", "1567-r24106-10cc5fcd00", None));
+        assert_eq!(
+            names_and_versions(&packages)[5],
+            ("libustream-mbedtls20201210", "2023.02.25~498f6e26-1", None)
+        );
+    }
+
+    #[test]
+    fn parses_opkg_upgradable() {
+        let packages = PackageManager::Opkg.parse_upgradable(OPKG_UPGRADABLE);
+        assert_eq!(
+            names_and_versions(&packages),
+            vec![
+                ("luci-app-firewall", "git-23.236.53405-98c6a29", Some("git-24.086.45142-09d5a38")),
+                ("ca-bundle", "20230311-1", Some("20240203-1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_apk_names_with_dashes() {
+        assert_eq!(split_apk_name_version("busybox-1.36.1-r1"), Some(("busybox", "1.36.1-r1")));
+        assert_eq!(split_apk_name_version("kmod-nf-conntrack-6.6.73-r1"), Some(("kmod-nf-conntrack", "6.6.73-r1")));
+        assert_eq!(
+            split_apk_name_version("libustream-mbedtls-2024.07.28~99bd3d2b-r1"),
+            Some(("libustream-mbedtls", "2024.07.28~99bd3d2b-r1"))
+        );
+        // Without a release suffix the last dash separates the version
+        assert_eq!(split_apk_name_version("luci-base-24.086"), Some(("luci-base", "24.086")));
+        assert_eq!(split_apk_name_version("nodash"), None);
+    }
+
+    #[test]
+    fn parses_apk_installed() {
+        let packages = parse_apk_installed(APK_INSTALLED);
+        assert_eq!(
+            names_and_versions(&packages),
+            vec![
+                ("busybox", "1.36.1-r1", None),
+                ("kmod-nf-conntrack", "6.6.73-r1", None),
+                ("luci-app-firewall", "24.086.45142~09d5a38-r1", None),
+                ("libustream-mbedtls", "2024.07.28~99bd3d2b-r1", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_apk_upgradable() {
+        let packages = PackageManager::Apk.parse_upgradable(APK_UPGRADABLE);
+        assert_eq!(
+            names_and_versions(&packages),
+            vec![
+                ("luci-app-firewall", "24.086.45142~09d5a38-r1", Some("25.010.12345~1a2b3c4-r1")),
+                ("kmod-nf-conntrack", "6.6.73-r1", Some("6.6.74-r1")),
+            ]
+        );
+    }
+
+    #[test]
+    fn empty_upgradable_lists() {
+        assert!(PackageManager::Opkg.parse_upgradable("").is_empty());
+        assert!(PackageManager::Apk.parse_upgradable("").is_empty());
+        // apk prints nothing but a trailing newline when everything is current
+        assert!(PackageManager::Apk.parse_upgradable("\n").is_empty());
+    }
+
+    #[test]
+    fn flags_risky_packages() {
+        assert!(is_risky("kernel"));
+        assert!(is_risky("kmod-nf-conntrack"));
+        assert!(!is_risky("luci-app-firewall"));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::config::{self, ConfigManager};
+use crate::netrc;
+use crate::commands::App;
+use crate::{client_options, dispatch, open_config, Cli, Commands};
+use anyhow::Result;
+use clap::{CommandFactory, FromArgMatches};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+// Interactive prompt that runs wrtcli commands against a selected device.
+// Each line is parsed exactly like a normal command line, with the selected
+// device used whenever a command is given no device name.
+pub async fn run_shell(mut selected: Option<String>) -> Result<()> {
+    let config = ConfigManager::new()?;
+    let history_path = config.shell_history_path();
+    // The read-only system config has nowhere to keep the history
+    let save_history = !config.is_read_only();
+    if let Some(parent) = history_path.parent().filter(|_| save_history) {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut editor = DefaultEditor::new()?;
+    // A missing history file just means this is the first session
+    let _ = editor.load_history(&history_path);
+
+    println!("wrtcli interactive shell. Type 'help' for commands, 'exit' or Ctrl-D to quit.");
+
+    loop {
+        let prompt = match &selected {
+            Some(name) => format!("wrtcli({})> ", name),
+            None => "wrtcli> ".to_string(),
+        };
+
+        let line = match editor.readline(&prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(err.into()),
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line)?;
+
+        let Some(words) = shlex::split(line) else {
+            eprintln!("Error: unbalanced quotes");
+            continue;
+        };
+
+        match words[0].as_str() {
+            "exit" | "quit" => break,
+            "help" => {
+                Cli::command().print_help()?;
+                println!();
+                println!("Shell commands:");
+                println!("  use <name>  Select the device used when no name is given");
+                println!("  exit        Leave the shell");
+            }
+            "use" => match words.get(1) {
+                Some(name) => match config.resolve_device(Some(name)) {
+                    Ok(device) => selected = Some(device.name),
+                    Err(err) => eprintln!("Error: {:#}", err),
+                },
+                None => eprintln!("Usage: use <name>"),
+            },
+            _ => {
+                let args = std::iter::once("wrtcli".to_string()).chain(words);
+                let parsed = Cli::command()
+                    .try_get_matches_from(args)
+                    .and_then(|matches| Ok((Cli::from_arg_matches(&matches)?, matches)));
+                let (cli, matches) = match parsed {
+                    Ok(parsed) => parsed,
+                    Err(err) => {
+                        let _ = err.print();
+                        continue;
+                    }
+                };
+
+                if matches!(cli.command, Commands::Shell) {
+                    eprintln!("Already in the interactive shell");
+                    continue;
+                }
+
+                // A --config or --profile given on this line applies to this
+                // line only
+                let path = match cli.profile {
+                    Some(_) => cli.config.clone(),
+                    None => Some(cli.config.clone().unwrap_or_else(|| config.config_path().to_path_buf())),
+                };
+                let line_config = match open_config(path, cli.profile.clone(), cli.verbose) {
+                    Ok(line_config) => line_config,
+                    Err(err) => {
+                        eprintln!("Error: {:#}", err);
+                        continue;
+                    }
+                };
+
+                netrc::set_enabled(!cli.no_netrc);
+                let options = client_options(&cli, &matches, line_config.as_ref());
+                let app = App::new(line_config, options);
+                let device = cli.device.or_else(|| selected.clone());
+                if let Err(err) = dispatch(&app, cli.command, device.as_deref()).await {
+                    eprintln!("Error: {:#}", err);
+                }
+            }
+        }
+    }
+
+    if save_history {
+        editor.save_history(&history_path)?;
+        config::set_private_mode(&history_path, 0o600)?;
+    }
+    Ok(())
+}
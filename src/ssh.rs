@@ -0,0 +1,304 @@
+use crate::config::{self, resolve_password};
+use crate::models::{split_host_port, Device};
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::engine::general_purpose::STANDARD_NO_PAD;
+use base64::Engine;
+use ssh2::{CheckResult, HashType, HostKeyType, KnownHostFileKind, Session};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const SSH_PORT: u16 = 22;
+const CHUNK_SIZE: usize = 32 * 1024;
+const PROGRESS_THRESHOLD: u64 = 1024 * 1024;
+
+// The file device host keys are recorded in, set from the config in use
+static KNOWN_HOSTS: OnceLock<PathBuf> = OnceLock::new();
+
+// Set by `--accept-new-host-key`: record unknown host keys without asking
+static ACCEPT_NEW: AtomicBool = AtomicBool::new(false);
+
+// Held while a host key is checked and recorded, so concurrent sessions
+// (--all commands) don't prompt over each other or race on the file
+static KNOWN_HOSTS_LOCK: Mutex<()> = Mutex::new(());
+
+pub fn set_known_hosts(path: PathBuf, accept_new: bool) {
+    let _ = KNOWN_HOSTS.set(path);
+    ACCEPT_NEW.store(accept_new, Ordering::Relaxed);
+}
+
+// The OpenSSH name of a host key type, as used in known_hosts files
+fn key_type_name(key_type: HostKeyType) -> Result<&'static str> {
+    Ok(match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => bail!("The device offered an SSH host key of an unknown type"),
+    })
+}
+
+// "SHA256:<base64>", the fingerprint `ssh-keygen -l` shows
+fn fingerprint(session: &Session) -> String {
+    let hash = session.host_key_hash(HashType::Sha256).unwrap_or_default();
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(hash))
+}
+
+// Ask on the terminal whether to trust a host key seen for the first time;
+// without a terminal the answer is no
+fn confirm_new_host(host: &str, key_type: &str, fingerprint: &str) -> Result<bool> {
+    if !std::io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    eprint!(
+        "🔑 The SSH host key of {} is not known yet.\n   {} key fingerprint is {}.\n   Trust it and continue? [y/N] ",
+        host, key_type, fingerprint
+    );
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Check the key the device offered against the known hosts file. A changed
+// key is refused; a new one is recorded once trusted, either on the
+// terminal or with --accept-new-host-key.
+fn verify_host_key(session: &Session, host: &str) -> Result<()> {
+    let (key, key_type) = session.host_key().context("The device did not offer an SSH host key")?;
+    let key_type = key_type_name(key_type)?;
+    let fingerprint = fingerprint(session);
+    let path = KNOWN_HOSTS.get().context("No known hosts file is configured for SSH host key checks")?;
+
+    let _lock = KNOWN_HOSTS_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut known_hosts = session.known_hosts()?;
+    if path.exists() {
+        known_hosts
+            .read_file(path, KnownHostFileKind::OpenSSH)
+            .context(format!("Failed to read {}", path.display()))?;
+    }
+
+    match known_hosts.check_port(host, SSH_PORT, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => bail!(
+            "The SSH host key of {} has changed ({} key {}), so the connection may be intercepted. \
+             If the device was reinstalled, remove its line from {} to trust the new key.",
+            host,
+            key_type,
+            fingerprint,
+            path.display()
+        ),
+        CheckResult::NotFound => {
+            if !ACCEPT_NEW.load(Ordering::Relaxed) && !confirm_new_host(host, key_type, &fingerprint)? {
+                bail!(
+                    "The SSH host key of {} ({} key {}) is not trusted; check it and rerun with --accept-new-host-key to record it",
+                    host,
+                    key_type,
+                    fingerprint
+                );
+            }
+            if let Some(dir) = path.parent() {
+                config::create_private_dir(dir)?;
+            }
+            let mut file = config::open_private(path, true).context(format!("Failed to open {}", path.display()))?;
+            writeln!(file, "{} {} {}", host, key_type, STANDARD.encode(key))
+                .context(format!("Failed to write {}", path.display()))?;
+            eprintln!("🔑 Recorded the SSH host key of {} ({}) in {}", host, fingerprint, path.display());
+            Ok(())
+        }
+        CheckResult::Failure => bail!("Failed to check the SSH host key of {} against {}", host, path.display()),
+    }
+}
+
+// Open an authenticated SSH session to the device using its stored
+// credentials. ssh2 is blocking, so callers should run this off the async
+// runtime (e.g. via `tokio::task::spawn_blocking`). A port stored with the
+// device address is the web server's; SSH always uses port 22.
+pub fn ssh_connect(device: &Device, connect_timeout: u64) -> Result<Session> {
+    let host = split_host_port(&device.ip).0;
+    let addr = (host, SSH_PORT)
+        .to_socket_addrs()
+        .context(format!("Failed to resolve {}", host))?
+        .next()
+        .context(format!("No address found for {}", host))?;
+
+    let tcp = TcpStream::connect_timeout(&addr, Duration::from_secs(connect_timeout))
+        .context(format!("Failed to connect to {} over SSH", addr))?;
+
+    let mut session = Session::new()?;
+    session.set_tcp_stream(tcp);
+    session.handshake().context("SSH handshake failed")?;
+    // Check who we are talking to before sending the password
+    verify_host_key(&session, host)?;
+
+    let password = resolve_password(device)?;
+    session
+        .userauth_password(&device.user, &password)
+        .context(format!("SSH authentication failed for user '{}'", device.user))?;
+
+    if !session.authenticated() {
+        bail!("SSH authentication failed for user '{}'", device.user);
+    }
+
+    Ok(session)
+}
+
+// Upload `file` to `remote` over SCP, creating it with `mode`
+pub fn scp_upload(session: &Session, file: &mut File, remote: &str, mode: i32) -> Result<u64> {
+    let size = file.metadata()?.len();
+    let mut channel = session
+        .scp_send(Path::new(remote), mode, size, None)
+        .context(format!("Failed to open '{}' on the device for writing", remote))?;
+
+    let copied = copy_with_progress(file, &mut channel, size, "Uploading")?;
+
+    channel.send_eof()?;
+    channel.wait_eof()?;
+    channel.close()?;
+    channel.wait_close()?;
+    Ok(copied)
+}
+
+// Download `remote` over SCP to `local`, failing if the transfer is cut
+// short. The data goes to a temporary file next to `local`, which only
+// replaces it once the download is complete, so a failed transfer leaves
+// an existing file as it was.
+pub fn scp_download(session: &Session, remote: &str, local: &Path) -> Result<u64> {
+    let (mut channel, stat) = session
+        .scp_recv(Path::new(remote))
+        .context(format!("Remote file '{}' does not exist or is not readable", remote))?;
+
+    let file_name = local.file_name().context(format!("'{}' does not name a file", local.display()))?;
+    let partial = local.with_file_name(format!(".{}.part", file_name.to_string_lossy()));
+    let received = receive(&mut channel, stat.size(), remote, &partial);
+    if let Err(err) = received {
+        let _ = fs::remove_file(&partial);
+        return Err(err);
+    }
+    fs::rename(&partial, local).context(format!("Failed to move the download to '{}'", local.display()))?;
+    Ok(stat.size())
+}
+
+fn receive(channel: &mut ssh2::Channel, size: u64, remote: &str, partial: &Path) -> Result<()> {
+    // Pulled files are often configs holding secrets, so the download stays
+    // private until it is complete; a leftover from an earlier run would
+    // keep its own mode, so it goes first
+    let _ = fs::remove_file(partial);
+    let mut file = config::open_private(partial, false).context(format!("Failed to create '{}'", partial.display()))?;
+    let copied = copy_with_progress(channel, &mut file, size, "Downloading")?;
+
+    channel.send_eof()?;
+    channel.wait_eof()?;
+    channel.close()?;
+    channel.wait_close()?;
+
+    if copied != size {
+        bail!("Incomplete download of '{}': got {} of {} bytes", remote, copied, size);
+    }
+    file.sync_all().context(format!("Failed to write '{}'", partial.display()))?;
+    Ok(())
+}
+
+// A command run on the device that exited with a non-zero status; wrtcli
+// exits with the same status
+#[derive(Debug)]
+pub struct RemoteExit {
+    pub command: String,
+    pub code: i32,
+}
+
+impl fmt::Display for RemoteExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "'{}' exited with status {}", self.command, self.code)
+    }
+}
+
+impl std::error::Error for RemoteExit {}
+
+// Run `command` through the device's shell and copy its output (stderr
+// merged into stdout) to `output` as it arrives. Returns the exit status.
+// With a `timeout` the command is abandoned once it runs longer than that.
+pub fn run_command<W: Write>(
+    session: &Session,
+    command: &str,
+    timeout: Option<Duration>,
+    output: &mut W,
+) -> Result<i32> {
+    let started = Instant::now();
+    let timed_out = || {
+        let seconds = timeout.unwrap_or_default().as_secs();
+        anyhow::anyhow!("'{}' did not finish within {} seconds", command, seconds)
+    };
+    session.set_timeout(timeout.map(|t| t.as_millis() as u32).unwrap_or(0));
+
+    let mut channel = session.channel_session()?;
+    channel
+        .exec(&format!("( {} ) 2>&1", command))
+        .context(format!("Failed to run '{}' on the device", command))?;
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = match channel.read(&mut buffer) {
+            Ok(read) => read,
+            Err(_) if timeout.is_some_and(|t| started.elapsed() >= t) => return Err(timed_out()),
+            Err(err) => return Err(err).context(format!("Failed to read the output of '{}'", command)),
+        };
+        if read == 0 {
+            break;
+        }
+        output.write_all(&buffer[..read])?;
+        output.flush()?;
+        if timeout.is_some_and(|t| started.elapsed() >= t) {
+            return Err(timed_out());
+        }
+    }
+
+    channel.wait_close()?;
+    session.set_timeout(0);
+    Ok(channel.exit_status()?)
+}
+
+// Copy everything from `reader` to `writer`, printing progress to stderr
+// for transfers large enough to take a noticeable amount of time
+pub fn copy_with_progress<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    total: u64,
+    label: &str,
+) -> Result<u64> {
+    let show_progress = total >= PROGRESS_THRESHOLD;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut copied = 0u64;
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+
+        if show_progress {
+            eprint!(
+                "\r📦 {}: {:.0}% ({} / {} bytes)",
+                label,
+                copied as f64 / total as f64 * 100.0,
+                copied,
+                total
+            );
+        }
+    }
+
+    if show_progress {
+        eprintln!();
+    }
+
+    Ok(copied)
+}
@@ -0,0 +1,123 @@
+use crate::models::Device;
+use crate::{ubus, zoneinfo};
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+const HOSTNAME_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// A hostname of dot-separated labels of letters, digits and dashes (RFC 1123)
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() || hostname.len() > 253 {
+        bail!("Hostnames must be 1 to 253 characters long");
+    }
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("'{}' is not a valid hostname: each dot-separated part takes 1 to 63 characters", hostname);
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("'{}' is not a valid hostname: only letters, digits, dashes and dots are allowed", hostname);
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            bail!("'{}' is not a valid hostname: parts must not start or end with a dash", hostname);
+        }
+    }
+    Ok(())
+}
+
+// The POSIX rule for a zone name from the built-in list
+pub fn zone_rule(zonename: &str) -> Result<&'static str> {
+    if let Some(rule) = zoneinfo::rule(zonename) {
+        return Ok(rule);
+    }
+    match zoneinfo::suggestions(zonename).as_slice() {
+        [] => bail!("Unknown time zone '{}'; use a zone name like Europe/Vienna or UTC", zonename),
+        suggestions => bail!("Unknown time zone '{}'; did you mean {}?", zonename, suggestions.join(" or ")),
+    }
+}
+
+// The first `system` section (@system[0], usually anonymous) with its values
+pub async fn section(client: &Client, device: &Device, session: &str) -> Result<(String, Value)> {
+    let sections = ubus::call(client, device, session, "uci", "get", json!({ "config": "system", "type": "system" }))
+        .await
+        .context(format!("Failed to read the system config of {}", device.name))?;
+    sections["values"]
+        .as_object()
+        .and_then(|sections| sections.iter().min_by_key(|(_, section)| section[".index"].as_u64().unwrap_or(u64::MAX)))
+        .map(|(name, section)| (name.clone(), section.clone()))
+        .with_context(|| format!("'{}' has no system section in /etc/config/system", device.name))
+}
+
+// Set and commit `values` in `section`, dropping what was staged if any of
+// it fails
+pub async fn set(client: &Client, device: &Device, session: &str, section: &str, values: Value) -> Result<()> {
+    let staged = ubus::call(
+        client,
+        device,
+        session,
+        "uci",
+        "set",
+        json!({ "config": "system", "section": section, "values": values }),
+    )
+    .await;
+    if let Err(err) = staged {
+        let _ = ubus::call(client, device, session, "uci", "revert", json!({ "config": "system" })).await;
+        return Err(err.context(format!("Failed to update the system config of {}", device.name)));
+    }
+    ubus::call(client, device, session, "uci", "commit", json!({ "config": "system" }))
+        .await
+        .context(format!("Failed to commit the system config on {}", device.name))?;
+    Ok(())
+}
+
+// Read the system section back and check that it holds the new time zone,
+// in case something on the device rewrote or rejected it
+pub async fn verify_zone(client: &Client, device: &Device, session: &str, zonename: &str, rule: &str) -> Result<()> {
+    let (_, current) = section(client, device, session).await?;
+    let applied = |key: &str| current[key].as_str().unwrap_or("(unset)").to_string();
+    let (current_zone, current_rule) = (applied("zonename"), applied("timezone"));
+    if current_zone != zonename || current_rule != rule {
+        bail!(
+            "'{}' has time zone {} ({}) after the reload instead of {} ({})",
+            device.name,
+            current_zone,
+            current_rule,
+            zonename,
+            rule
+        );
+    }
+    Ok(())
+}
+
+pub async fn board_hostname(client: &Client, device: &Device, session: &str) -> Result<String> {
+    let board = ubus::call(client, device, session, "system", "board", json!({})).await?;
+    Ok(board["hostname"].as_str().unwrap_or_default().to_string())
+}
+
+// Wait for `system board` to report the new hostname, which the system
+// service applies when it is reloaded
+pub async fn wait_for_hostname(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    hostname: &str,
+    timeout: Duration,
+) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        let current = board_hostname(client, device, session).await?;
+        if current == hostname {
+            return Ok(());
+        }
+        if started.elapsed() >= timeout {
+            bail!(
+                "'{}' still reports the hostname '{}' {} seconds after the system service was reloaded",
+                device.name,
+                current,
+                timeout.as_secs()
+            );
+        }
+        tokio::time::sleep(HOSTNAME_POLL_INTERVAL).await;
+    }
+}
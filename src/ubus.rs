@@ -0,0 +1,505 @@
+use crate::config::resolve_password;
+use crate::logging;
+use crate::models::{split_host_port, Device, LogLevel, OutputFormat, Secret, SettingSource};
+use anyhow::{bail, Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const USER_AGENT: &str = concat!("wrtcli/", env!("CARGO_PKG_VERSION"));
+const ANONYMOUS_SESSION: &str = "00000000000000000000000000000000";
+const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 300;
+
+// Sessions obtained during this process, keyed by device address, so that
+// repeated commands (e.g. in `wrtcli shell`) don't log in every time
+static SESSIONS: LazyLock<Mutex<HashMap<String, CachedSession>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+struct CachedSession {
+    token: Secret,
+    timeout: Duration,
+    expires_at: Instant,
+    // ubus objects (patterns) mapped to the methods (patterns) the session
+    // may call, as granted by rpcd; `None` when the login reply had no ACLs
+    acls: Option<HashMap<String, Vec<String>>>,
+}
+
+fn cache_key(device: &Device) -> String {
+    format!("{}@{}", device.user, device.ip)
+}
+
+fn cached_session(device: &Device) -> Option<String> {
+    let sessions = SESSIONS.lock().unwrap();
+    sessions
+        .get(&cache_key(device))
+        .filter(|session| session.expires_at > Instant::now())
+        .map(|session| session.token.expose().to_string())
+}
+
+// rpcd extends a session's lifetime every time it is used
+fn touch_session(token: &str) {
+    let mut sessions = SESSIONS.lock().unwrap();
+    for session in sessions.values_mut().filter(|s| s.token.expose() == token) {
+        session.expires_at = Instant::now() + session.timeout;
+    }
+}
+
+// Match an rpcd ACL pattern, where `*` matches any run of characters
+fn acl_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(remaining) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=remaining.len())
+                .filter(|&i| remaining.is_char_boundary(i))
+                .any(|i| acl_match(rest, &remaining[i..]))
+        }
+    }
+}
+
+fn parse_acls(login_data: &Value) -> Option<HashMap<String, Vec<String>>> {
+    let ubus = login_data["acls"]["ubus"].as_object()?;
+    Some(
+        ubus.iter()
+            .map(|(object, methods)| {
+                let methods = methods
+                    .as_array()
+                    .map(|m| m.iter().filter_map(|m| m.as_str().map(str::to_string)).collect())
+                    .unwrap_or_default();
+                (object.clone(), methods)
+            })
+            .collect(),
+    )
+}
+
+// Fail fast when the session's ACLs don't allow calling `object.method`,
+// rather than letting rpcd reject the call with a generic error
+fn ensure_access(device: &Device, token: &str, object: &str, method: &str) -> Result<()> {
+    let sessions = SESSIONS.lock().unwrap();
+    let Some(acls) = sessions
+        .values()
+        .find(|s| s.token.expose() == token)
+        .and_then(|s| s.acls.as_ref())
+    else {
+        return Ok(());
+    };
+
+    let permitted = acls.iter().any(|(pattern, methods)| {
+        acl_match(pattern, object) && methods.iter().any(|m| acl_match(m, method))
+    });
+    if !permitted {
+        bail!(
+            "User '{}' lacks access to ubus object '{}' (method '{}'); \
+             grant it in the rpcd ACLs under /usr/share/rpcd/acl.d/",
+            device.user,
+            object,
+            method
+        );
+    }
+
+    Ok(())
+}
+
+// Whether a login reply's ACLs allow calling `object.method`; replies
+// without ACLs allow everything
+pub fn acl_allows(login_data: &Value, object: &str, method: &str) -> bool {
+    let Some(acls) = parse_acls(login_data) else {
+        return true;
+    };
+    acls.iter().any(|(pattern, methods)| {
+        acl_match(pattern, object) && methods.iter().any(|m| acl_match(m, method))
+    })
+}
+
+fn forget_session(token: &str) {
+    SESSIONS.lock().unwrap().retain(|_, s| s.token.expose() != token);
+}
+
+// Drop any cached session for the device so the next login is fresh, e.g.
+// after a reboot has invalidated it
+pub fn invalidate_session(device: &Device) {
+    SESSIONS.lock().unwrap().remove(&cache_key(device));
+}
+
+// Global options, resolved from the command line flags, environment and
+// the config file's [settings]
+#[derive(Clone)]
+pub struct ClientOptions {
+    pub connect_timeout: u64,
+    // Extra HTTP headers from `--header`, overriding the device's own
+    pub headers: Vec<(String, String)>,
+    // Devices queried at once by `--all` commands
+    pub concurrency: usize,
+    pub output: OutputFormat,
+    // Where device backups are saved, when not the default location
+    pub backup_dir: Option<std::path::PathBuf>,
+    pub log_level: Option<LogLevel>,
+    pub log_file: Option<std::path::PathBuf>,
+    // Where each of the settings above came from, keyed by setting name
+    pub sources: Vec<(&'static str, SettingSource)>,
+}
+
+impl ClientOptions {
+    // Whether to print JSON, given the command's own `--json` flag
+    pub fn json(&self, flag: bool) -> bool {
+        flag || self.output == OutputFormat::Json
+    }
+
+    pub fn source(&self, key: &str) -> SettingSource {
+        self.sources
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, source)| *source)
+            .unwrap_or(SettingSource::Default)
+    }
+}
+
+// Hands out the HTTP clients used to talk to devices. Devices only differ in
+// their configured headers, so one client (and its connection pool) is built
+// per distinct set of headers and shared for the rest of the invocation.
+pub struct ClientFactory {
+    connect_timeout: u64,
+    headers: Vec<(String, String)>,
+    proxy: Option<reqwest::Proxy>,
+    clients: Mutex<HashMap<Vec<(String, String)>, Client>>,
+}
+
+impl ClientFactory {
+    pub fn new(options: &ClientOptions) -> Self {
+        Self {
+            connect_timeout: options.connect_timeout,
+            headers: options.headers.clone(),
+            proxy: None,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Clients that send every request to the HTTP proxy at `url` instead of
+    // the device, so tests can answer for devices that don't exist
+    #[cfg(test)]
+    pub fn with_proxy(options: &ClientOptions, url: &str) -> Self {
+        Self { proxy: Some(reqwest::Proxy::http(url).unwrap()), ..Self::new(options) }
+    }
+
+    pub fn client(&self, device: &Device) -> Result<Client> {
+        let mut key: Vec<(String, String)> = device.headers.clone().into_iter().collect();
+        key.sort();
+
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get(&key) {
+            return Ok(client.clone());
+        }
+        let client = self.build(&key)?;
+        clients.insert(key, client.clone());
+        Ok(client)
+    }
+
+    // The connect timeout is kept short so unreachable devices fail fast,
+    // while the overall request timeout still allows for slow responses.
+    // `--header` values override the device's own headers.
+    fn build(&self, configured: &[(String, String)]) -> Result<Client> {
+        let mut headers = HeaderMap::new();
+        for (key, value) in configured.iter().chain(&self.headers) {
+            let name = HeaderName::from_bytes(key.as_bytes())
+                .context(format!("Invalid HTTP header name '{}'", key))?;
+            let value = HeaderValue::from_str(value)
+                .context(format!("Invalid value for HTTP header '{}'", key))?;
+            headers.insert(name, value);
+        }
+
+        let mut builder = Client::builder()
+            .user_agent(USER_AGENT)
+            .default_headers(headers)
+            .connect_timeout(Duration::from_secs(self.connect_timeout))
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS));
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        Ok(builder.build()?)
+    }
+}
+
+// Map a ubus status code to its name in libubus
+fn status_name(code: u64) -> &'static str {
+    match code {
+        1 => "invalid command",
+        2 => "invalid argument",
+        3 => "method not found",
+        4 => "not found",
+        5 => "no data",
+        6 => "permission denied",
+        7 => "timeout",
+        8 => "not supported",
+        10 => "connection failed",
+        _ => "unknown error",
+    }
+}
+
+// Send a raw JSON-RPC `call` and return the full `result` array
+async fn rpc_call(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    object: &str,
+    method: &str,
+    params: Value,
+) -> Result<Value> {
+    if session != ANONYMOUS_SESSION {
+        logging::register_secret(session);
+    }
+    tracing::debug!("ubus call {}.{} on {} as {}", object, method, device.ip, device.user);
+
+    let response = client
+        .post(device.ubus_url())
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "call",
+            "params": [session, object, method, params]
+        }))
+        .send()
+        .await
+        .context(format!("Failed to reach ubus on {}", device.ip))?;
+
+    let data = response.json::<Value>().await?;
+    if let Some(token) = data["result"][1]["ubus_rpc_session"].as_str() {
+        logging::register_secret(token);
+    }
+    tracing::trace!("ubus reply from {}: {}", device.ip, data);
+    if let Some(message) = data["error"]["message"].as_str() {
+        tracing::warn!("ubus call {}.{} on {} failed: {}", object, method, device.ip, message);
+        forget_session(session);
+        bail!("ubus call {}.{} failed: {}", object, method, message);
+    }
+
+    let code = data["result"][0].as_u64().unwrap_or(0);
+    if code != 0 {
+        bail!("ubus call {}.{} failed: {}", object, method, status_name(code));
+    }
+
+    Ok(data["result"].clone())
+}
+
+// Log in to the device and return the ubus session token
+pub async fn login(client: &Client, device: &Device) -> Result<String> {
+    if let Some(token) = cached_session(device) {
+        return Ok(token);
+    }
+
+    let data = login_details(client, device).await?;
+    let session = data["ubus_rpc_session"]
+        .as_str()
+        .context("Failed to get session token")?;
+
+    Ok(session.to_string())
+}
+
+// Perform a fresh login and return rpcd's full reply (session token,
+// timeout, granted ACLs and user data). The session is cached for reuse by
+// `login`.
+pub async fn login_details(client: &Client, device: &Device) -> Result<Value> {
+    let password = resolve_password(device)?;
+    logging::register_secret(&password);
+    tracing::info!("Logging in to {} as {}", device.ip, device.user);
+    let result = rpc_call(
+        client,
+        device,
+        ANONYMOUS_SESSION,
+        "session",
+        "login",
+        json!({
+            "username": device.user,
+            "password": password
+        }),
+    )
+    .await?;
+
+    let session = result[1]["ubus_rpc_session"]
+        .as_str()
+        .context("Failed to get session token")?;
+
+    // Expire the cached session a little early to avoid racing rpcd
+    let timeout = Duration::from_secs(
+        result[1]["timeout"]
+            .as_u64()
+            .unwrap_or(DEFAULT_SESSION_TIMEOUT_SECS)
+            .saturating_sub(10),
+    );
+    SESSIONS.lock().unwrap().insert(
+        cache_key(device),
+        CachedSession {
+            token: Secret::new(session),
+            timeout,
+            expires_at: Instant::now() + timeout,
+            acls: parse_acls(&result[1]),
+        },
+    );
+
+    Ok(result[1].clone())
+}
+
+// Call a ubus method and return its reply data (`null` when there is none)
+pub async fn call(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    object: &str,
+    method: &str,
+    params: Value,
+) -> Result<Value> {
+    ensure_access(device, session, object, method)?;
+    let result = rpc_call(client, device, session, object, method, params).await?;
+    touch_session(session);
+    Ok(result[1].clone())
+}
+
+// Output of a program run on the device
+pub struct ExecOutput {
+    pub code: i64,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Run `command` (an absolute path) on the device through rpcd's `file exec`
+// and wait for it to finish. The session's ACLs must allow executing it.
+pub async fn exec(client: &Client, device: &Device, session: &str, command: &str, params: &[&str]) -> Result<ExecOutput> {
+    let result = call(
+        client,
+        device,
+        session,
+        "file",
+        "exec",
+        json!({ "command": command, "params": params }),
+    )
+    .await
+    .context(format!("Failed to run {} on {}", command, device.name))?;
+
+    Ok(ExecOutput {
+        code: result["code"].as_i64().unwrap_or(0),
+        stdout: result["stdout"].as_str().unwrap_or_default().to_string(),
+        stderr: result["stderr"].as_str().unwrap_or_default().to_string(),
+    })
+}
+
+// List ubus objects matching `pattern` with their methods and argument
+// signatures. Unlike `call`, `list` takes no session.
+pub async fn list(client: &Client, device: &Device, pattern: &str) -> Result<Value> {
+    let response = client
+        .post(device.ubus_url())
+        .json(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "list",
+            "params": [pattern]
+        }))
+        .send()
+        .await
+        .context(format!("Failed to reach ubus on {}", device.ip))?;
+
+    let data = response.json::<Value>().await?;
+    if let Some(message) = data["error"]["message"].as_str() {
+        bail!("ubus list failed: {}", message);
+    }
+
+    Ok(data["result"].clone())
+}
+
+// The device's address and the local address this machine reaches it from,
+// found by connecting a UDP socket (nothing is sent)
+pub fn route_to(device: &Device) -> Result<(IpAddr, IpAddr)> {
+    let host = split_host_port(&device.ip).0;
+    let remote = (host, 80)
+        .to_socket_addrs()
+        .context(format!("Failed to resolve {}", host))?
+        .next()
+        .context(format!("No address found for {}", host))?;
+    let unspecified: IpAddr = if remote.is_ipv4() {
+        Ipv4Addr::UNSPECIFIED.into()
+    } else {
+        Ipv6Addr::UNSPECIFIED.into()
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0)).context("Failed to open a UDP socket")?;
+    socket.connect(remote).context(format!("No route to {}", remote.ip()))?;
+    Ok((remote.ip(), socket.local_addr()?.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    // Answer one HTTP request on a local port and hand back its header lines
+    fn capture_request() -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let handle = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = Vec::new();
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                lines.push(line.trim().to_lowercase());
+            }
+            let mut stream = stream;
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}").unwrap();
+            lines
+        });
+        (address, handle)
+    }
+
+    fn options(headers: &[(&str, &str)]) -> ClientOptions {
+        ClientOptions {
+            connect_timeout: 3,
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            concurrency: 1,
+            output: OutputFormat::Text,
+            backup_dir: None,
+            log_level: None,
+            log_file: None,
+            sources: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_device_headers() {
+        let (address, request) = capture_request();
+        let mut device = Device::new("router1".into(), address, "root".into(), String::new());
+        device.headers.insert("X-Proxy-Token".into(), "abc123".into());
+        device.headers.insert("X-Tenant".into(), "lab".into());
+
+        // --header overrides the device's own value for the same name
+        let factory = ClientFactory::new(&options(&[("X-Tenant", "office")]));
+        let client = factory.client(&device).unwrap();
+        client.post(device.ubus_url()).send().await.unwrap();
+
+        let lines = request.join().unwrap();
+        assert!(lines.contains(&"x-proxy-token: abc123".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"x-tenant: office".to_string()), "{:?}", lines);
+        assert!(!lines.contains(&"x-tenant: lab".to_string()), "{:?}", lines);
+    }
+
+    #[test]
+    fn shares_clients_between_devices_with_the_same_headers() {
+        let factory = ClientFactory::new(&options(&[]));
+        let a = Device::new("a".into(), "10.0.0.1".into(), "root".into(), String::new());
+        let b = Device::new("b".into(), "10.0.0.2".into(), "root".into(), String::new());
+        let mut c = b.clone();
+        c.headers.insert("X-Proxy-Token".into(), "abc123".into());
+        factory.client(&a).unwrap();
+        factory.client(&b).unwrap();
+        factory.client(&c).unwrap();
+        assert_eq!(factory.clients.lock().unwrap().len(), 2);
+    }
+}
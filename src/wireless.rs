@@ -0,0 +1,463 @@
+use crate::models::Device;
+use crate::ubus;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, Timelike};
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// `wifi disable --timer` re-enables the radios from a one-off entry in root's
+// crontab, marked so it can be found again and removes itself once it ran
+const CRONTAB: &str = "/etc/crontabs/root";
+const CRON_UPDATE: &str = "/etc/crontabs/cron.update";
+const TIMER_MARKER: &str = "# wrtcli wifi timer";
+
+// Radios are `wifi-device` sections of /etc/config/wireless, changed through
+// the ubus `uci` object; netifd reports their live state in `network.wireless`
+#[derive(Debug, Clone, Serialize)]
+pub struct RadioStatus {
+    pub up: bool,
+    pub pending: bool,
+    pub disabled: bool,
+    // Network devices (e.g. "phy0-ap0") the radio's interfaces are running on
+    pub interfaces: Vec<String>,
+}
+
+// Fail with a clear message when the device has no wireless configuration,
+// i.e. no wifi driver is installed
+async fn ensure_wireless(client: &Client, device: &Device, session: &str) -> Result<()> {
+    let configs = ubus::call(client, device, session, "uci", "configs", json!({})).await?;
+    let has_wireless = configs["configs"]
+        .as_array()
+        .map(|configs| configs.iter().any(|c| c.as_str() == Some("wireless")))
+        .unwrap_or(false);
+    if !has_wireless {
+        bail!(
+            "'{}' has no wireless configuration (/etc/config/wireless); install a wifi driver package to use wifi commands",
+            device.name
+        );
+    }
+    Ok(())
+}
+
+// Names of the device's radios, sorted
+pub async fn radios(client: &Client, device: &Device, session: &str) -> Result<Vec<String>> {
+    ensure_wireless(client, device, session).await?;
+    let sections = ubus::call(
+        client,
+        device,
+        session,
+        "uci",
+        "get",
+        json!({ "config": "wireless", "type": "wifi-device" }),
+    )
+    .await?;
+
+    let mut radios: Vec<String> = sections["values"]
+        .as_object()
+        .map(|values| values.keys().cloned().collect())
+        .unwrap_or_default();
+    if radios.is_empty() {
+        bail!("'{}' has no radios configured in /etc/config/wireless", device.name);
+    }
+    radios.sort();
+    Ok(radios)
+}
+
+// A `wifi-iface` section: one network (SSID) on one radio
+#[derive(Debug, Clone)]
+pub struct WifiIface {
+    pub section: String,
+    pub radio: String,
+    pub ssid: String,
+    pub encryption: String,
+}
+
+pub async fn ifaces(client: &Client, device: &Device, session: &str) -> Result<Vec<WifiIface>> {
+    ensure_wireless(client, device, session).await?;
+    let sections = ubus::call(
+        client,
+        device,
+        session,
+        "uci",
+        "get",
+        json!({ "config": "wireless", "type": "wifi-iface" }),
+    )
+    .await?;
+
+    let mut ifaces: Vec<WifiIface> = sections["values"]
+        .as_object()
+        .map(|values| {
+            values
+                .iter()
+                .map(|(section, values)| WifiIface {
+                    section: section.clone(),
+                    radio: values["device"].as_str().unwrap_or_default().to_string(),
+                    ssid: values["ssid"].as_str().unwrap_or_default().to_string(),
+                    encryption: values["encryption"].as_str().unwrap_or("none").to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    ifaces.sort_by(|a, b| a.section.cmp(&b.section));
+    Ok(ifaces)
+}
+
+// The interfaces named `target`, by UCI section name or by SSID. An SSID
+// usually matches one interface per band.
+pub fn find_ifaces(device: &Device, ifaces: Vec<WifiIface>, target: &str) -> Result<Vec<WifiIface>> {
+    let matches: Vec<WifiIface> = ifaces
+        .into_iter()
+        .filter(|iface| iface.section == target || iface.ssid == target)
+        .collect();
+    if matches.is_empty() {
+        bail!("'{}' has no wifi interface with SSID or section name '{}'", device.name, target);
+    }
+    Ok(matches)
+}
+
+pub fn validate_ssid(ssid: &str) -> Result<()> {
+    if ssid.is_empty() || ssid.len() > 32 {
+        bail!("An SSID must be 1 to 32 bytes long");
+    }
+    Ok(())
+}
+
+// A WPA passphrase is 8 to 63 printable ASCII characters, or the 64 hex
+// digit key itself
+pub fn validate_passphrase(key: &str) -> Result<()> {
+    let printable = key.chars().all(|c| c.is_ascii() && !c.is_ascii_control());
+    let hex_key = key.len() == 64 && key.chars().all(|c| c.is_ascii_hexdigit());
+    let passphrase = printable && (8..=63).contains(&key.len());
+    if !hex_key && !passphrase {
+        bail!("A wifi passphrase must be 8 to 63 printable ASCII characters (or a 64 digit hex key)");
+    }
+    Ok(())
+}
+
+// A passphrase only takes effect on an encrypted network
+pub fn ensure_encrypted(device: &Device, iface: &WifiIface) -> Result<()> {
+    if iface.encryption == "none" {
+        bail!(
+            "{} ('{}') on '{}' is an open network; set its encryption before giving it a passphrase",
+            iface.section,
+            iface.ssid,
+            device.name
+        );
+    }
+    Ok(())
+}
+
+// Stage a new SSID and/or passphrase for an interface; `commit` applies it
+pub async fn set_iface(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    iface: &WifiIface,
+    ssid: Option<&str>,
+    key: Option<&str>,
+) -> Result<()> {
+    let mut values = serde_json::Map::new();
+    if let Some(ssid) = ssid {
+        values.insert("ssid".to_string(), json!(ssid));
+    }
+    if let Some(key) = key {
+        values.insert("key".to_string(), json!(key));
+    }
+    ubus::call(
+        client,
+        device,
+        session,
+        "uci",
+        "set",
+        json!({ "config": "wireless", "section": iface.section, "values": values }),
+    )
+    .await
+    .context(format!("Failed to update {} on {}", iface.section, device.name))?;
+    Ok(())
+}
+
+// `radio` when given (it must exist), otherwise every radio
+pub fn select_radios(device: &Device, radios: Vec<String>, radio: Option<&str>) -> Result<Vec<String>> {
+    let Some(radio) = radio else {
+        return Ok(radios);
+    };
+    if !radios.iter().any(|r| r == radio) {
+        bail!("'{}' has no radio '{}' (radios: {})", device.name, radio, radios.join(", "));
+    }
+    Ok(vec![radio.to_string()])
+}
+
+// Set `disabled` on each radio and commit the wireless config
+pub async fn set_disabled(client: &Client, device: &Device, session: &str, radios: &[String], disabled: bool) -> Result<()> {
+    for radio in radios {
+        ubus::call(
+            client,
+            device,
+            session,
+            "uci",
+            "set",
+            json!({
+                "config": "wireless",
+                "section": radio,
+                "values": { "disabled": if disabled { "1" } else { "0" } }
+            }),
+        )
+        .await
+        .context(format!("Failed to update {} on {}", radio, device.name))?;
+    }
+    commit(client, device, session).await
+}
+
+pub async fn commit(client: &Client, device: &Device, session: &str) -> Result<()> {
+    ubus::call(client, device, session, "uci", "commit", json!({ "config": "wireless" }))
+        .await
+        .context(format!("Failed to commit the wireless config on {}", device.name))?;
+    Ok(())
+}
+
+// Drop changes staged in this session, so a later commit doesn't pick up a
+// half-applied update
+pub async fn revert(client: &Client, device: &Device, session: &str) -> Result<()> {
+    ubus::call(client, device, session, "uci", "revert", json!({ "config": "wireless" })).await?;
+    Ok(())
+}
+
+// The equivalent of `wifi reload`: netifd rereads the config and brings the
+// radios up or down to match it
+pub async fn reload(client: &Client, device: &Device, session: &str) -> Result<()> {
+    ubus::call(client, device, session, "network", "reload", json!({}))
+        .await
+        .context(format!("Failed to reload the network on {}", device.name))?;
+    Ok(())
+}
+
+pub async fn status(client: &Client, device: &Device, session: &str) -> Result<BTreeMap<String, RadioStatus>> {
+    let data = ubus::call(client, device, session, "network.wireless", "status", json!({})).await?;
+    let radios = data
+        .as_object()
+        .map(|radios| {
+            radios
+                .iter()
+                .map(|(name, radio)| (name.clone(), parse_radio_status(radio)))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(radios)
+}
+
+fn parse_radio_status(radio: &Value) -> RadioStatus {
+    let interfaces = radio["interfaces"]
+        .as_array()
+        .map(|interfaces| {
+            interfaces
+                .iter()
+                .filter_map(|iface| iface["ifname"].as_str().map(str::to_string))
+                .collect()
+        })
+        .unwrap_or_default();
+    RadioStatus {
+        up: radio["up"].as_bool().unwrap_or(false),
+        pending: radio["pending"].as_bool().unwrap_or(false),
+        disabled: radio["disabled"].as_bool().unwrap_or(false),
+        interfaces,
+    }
+}
+
+// Take the radios (or just `radio`) down or bring them up, like `wifi down`
+// and `wifi up`; netifd does the work after the call returns
+pub async fn down(client: &Client, device: &Device, session: &str, radio: Option<&str>) -> Result<()> {
+    radio_call(client, device, session, "down", radio).await
+}
+
+pub async fn up(client: &Client, device: &Device, session: &str, radio: Option<&str>) -> Result<()> {
+    radio_call(client, device, session, "up", radio).await
+}
+
+async fn radio_call(client: &Client, device: &Device, session: &str, method: &str, radio: Option<&str>) -> Result<()> {
+    let params = match radio {
+        Some(radio) => json!({ "device": radio }),
+        None => json!({}),
+    };
+    ubus::call(client, device, session, "network.wireless", method, params)
+        .await
+        .context(format!("Failed to bring the wireless {} on {}", method, device.name))?;
+    Ok(())
+}
+
+// Whether this machine is one of the device's wifi clients: its address is
+// looked up in the device's ARP table and the MAC address among the
+// stations hostapd reports. `None` when that can't be determined (e.g. the
+// ACLs don't allow it, or the device is reached through a router).
+pub async fn connected_over_wifi(client: &Client, device: &Device, session: &str) -> Option<bool> {
+    let (_, address) = ubus::route_to(device).ok()?;
+    let address = address.to_string();
+    let arp = ubus::call(client, device, session, "file", "read", json!({ "path": "/proc/net/arp" }))
+        .await
+        .ok()?;
+    let mac = arp["data"].as_str()?.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [ip, _, _, mac, ..] if *ip == address => Some(mac.to_lowercase()),
+            _ => None,
+        }
+    })?;
+
+    let objects = ubus::list(client, device, "hostapd.*").await.ok()?;
+    for object in objects.as_object()?.keys() {
+        let clients = ubus::call(client, device, session, object, "get_clients", json!({})).await.ok()?;
+        if clients["clients"].as_object().is_some_and(|clients| clients.contains_key(&mac)) {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+// Poll `network.wireless status` until every radio in `radios` is up (or
+// down, with `up` false) and not pending, or `timeout` passes. Returns the
+// last status of those radios and whether they all settled.
+pub async fn wait_for_radios(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    radios: &[String],
+    up: bool,
+    timeout: Duration,
+) -> Result<(BTreeMap<String, RadioStatus>, bool)> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let mut current = status(client, device, session).await?;
+        current.retain(|name, _| radios.contains(name));
+        let settled = radios
+            .iter()
+            .all(|radio| current.get(radio).is_some_and(|s| s.up == up && !s.pending));
+        if settled || Instant::now() >= deadline {
+            return Ok((current, settled));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+// Poll `network.wireless status` until every interface in `ifaces` runs
+// with `ssid` and `key` (where given), or `timeout` passes. Interfaces on
+// disabled radios aren't running and are not waited for. Returns whether
+// the new settings showed up.
+pub async fn wait_for_ifaces(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    ifaces: &[WifiIface],
+    ssid: Option<&str>,
+    key: Option<&str>,
+    timeout: Duration,
+) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = ubus::call(client, device, session, "network.wireless", "status", json!({})).await?;
+        let applied = ifaces.iter().all(|iface| {
+            let radio = &status[&iface.radio];
+            if radio["disabled"].as_bool().unwrap_or(false) {
+                return true;
+            }
+            let running = radio["interfaces"]
+                .as_array()
+                .and_then(|interfaces| interfaces.iter().find(|i| i["section"].as_str() == Some(&iface.section)));
+            running.is_some_and(|running| {
+                let config = &running["config"];
+                ssid.is_none_or(|ssid| config["ssid"].as_str() == Some(ssid))
+                    && key.is_none_or(|key| config["key"].as_str().is_none_or(|running_key| running_key == key))
+            })
+        });
+        if applied || Instant::now() >= deadline {
+            return Ok(applied);
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn read_crontab(client: &Client, device: &Device, session: &str) -> Result<String> {
+    // A missing crontab is just empty
+    if ubus::call(client, device, session, "file", "stat", json!({ "path": CRONTAB })).await.is_err() {
+        return Ok(String::new());
+    }
+    let file = ubus::call(client, device, session, "file", "read", json!({ "path": CRONTAB }))
+        .await
+        .context(format!("Failed to read {} on {}", CRONTAB, device.name))?;
+    Ok(file["data"].as_str().unwrap_or_default().to_string())
+}
+
+// Write root's crontab and tell busybox crond to reread it
+async fn write_crontab(client: &Client, device: &Device, session: &str, lines: &[&str]) -> Result<()> {
+    let mut data = lines.join("\n");
+    if !data.is_empty() {
+        data.push('\n');
+    }
+    ubus::call(client, device, session, "file", "write", json!({ "path": CRONTAB, "data": data }))
+        .await
+        .context(format!("Failed to write {} on {}", CRONTAB, device.name))?;
+    ubus::call(client, device, session, "file", "write", json!({ "path": CRON_UPDATE, "data": "root\n" }))
+        .await
+        .context(format!("Failed to notify crond on {}", device.name))?;
+    Ok(())
+}
+
+// The device's clock, which cron entries are in; `system info` reports the
+// local time as seconds since the epoch
+async fn device_local_time(client: &Client, device: &Device, session: &str) -> Result<i64> {
+    let info = ubus::call(client, device, session, "system", "info", json!({})).await?;
+    info["localtime"]
+        .as_i64()
+        .context("Device did not report its local time")
+}
+
+// Schedule re-enabling `radios` after `delay`, replacing any earlier timer.
+// Returns the device-local time it will run at ("YYYY-MM-DD HH:MM").
+pub async fn schedule_enable(
+    client: &Client,
+    device: &Device,
+    session: &str,
+    radios: &[String],
+    delay: Duration,
+) -> Result<String> {
+    // Cron runs on whole minutes, so round up
+    let at = device_local_time(client, device, session).await? + delay.as_secs() as i64;
+    let at = DateTime::from_timestamp((at + 59).div_euclid(60) * 60, 0).context("Timer is out of range")?;
+
+    let enable: Vec<String> = radios
+        .iter()
+        .map(|radio| format!("/sbin/uci set wireless.{}.disabled=0", radio))
+        .collect();
+    let entry = format!(
+        "{} {} {} {} * {} && /sbin/uci commit wireless && /sbin/wifi reload; /bin/sed -i '/{}$/d' {}; echo root > {} {}",
+        at.minute(),
+        at.hour(),
+        at.day(),
+        at.month(),
+        enable.join(" && "),
+        TIMER_MARKER,
+        CRONTAB,
+        CRON_UPDATE,
+        TIMER_MARKER
+    );
+
+    let crontab = read_crontab(client, device, session).await?;
+    let mut lines: Vec<&str> = crontab.lines().filter(|line| !line.ends_with(TIMER_MARKER)).collect();
+    lines.push(&entry);
+    write_crontab(client, device, session, &lines).await?;
+    Ok(at.format("%Y-%m-%d %H:%M").to_string())
+}
+
+// Remove a pending `--timer` entry; returns whether there was one
+pub async fn cancel_timer(client: &Client, device: &Device, session: &str) -> Result<bool> {
+    let crontab = read_crontab(client, device, session).await?;
+    let lines: Vec<&str> = crontab.lines().filter(|line| !line.ends_with(TIMER_MARKER)).collect();
+    if lines.len() == crontab.lines().count() {
+        return Ok(false);
+    }
+    write_crontab(client, device, session, &lines).await?;
+    Ok(true)
+}